@@ -0,0 +1,16 @@
+pub(crate) mod inotify;
+pub(crate) mod poll;
+
+use std::time::Duration;
+
+/// How changes of the folder are noticed.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Backend {
+    /// Linux inotify events, files are reported as soon as they are ready.
+    #[default]
+    Inotify,
+    /// Listings of the folder compared at the interval, a file is reported
+    /// once it stays unchanged between two listings.
+    /// Works on network, FUSE and overlay filesystems where inotify events are unreliable.
+    Poll(Duration),
+}
@@ -0,0 +1,322 @@
+use crate::{
+    error::Error,
+    filter::Filter,
+    notifier::{list_files, scan, Backlog, Options, Sender},
+};
+use anyhow::{Context as _, Result};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{error, trace};
+
+/// Watched folders by their watch descriptors, paths are relative to the root folder.
+type Folders = Arc<Mutex<HashMap<WatchDescriptor, PathBuf>>>;
+
+/// Watches of the folder kept by the notifier to stop the reader.
+pub(crate) struct Watcher {
+    watches: Watches,
+    folders: Folders,
+    root: WatchDescriptor,
+}
+
+impl Watcher {
+    /// Removes the watches, so the blocking reader stops.
+    pub(crate) fn close(&mut self) -> Result<()> {
+        // Subfolders go first, the reader stops once the root watch is removed.
+        let subfolders = self
+            .folders
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|wd| **wd != self.root)
+            .cloned()
+            .collect::<Vec<_>>();
+        for wd in subfolders {
+            if let Err(why) = self.watches.remove(wd) {
+                trace!("failed removing watcher of subfolder: {}", why);
+            }
+        }
+
+        self.watches
+            .remove(self.root.clone())
+            .inspect(|_| trace!("watcher removed"))
+            .context("Removing watcher")
+    }
+}
+
+pub(crate) fn watch(options: Options, backlog: Backlog, sender: Sender) -> Result<Watcher> {
+    let Options {
+        dir,
+        recursive,
+        filter,
+        debounce,
+    } = options;
+
+    let mut mask =
+        WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::DELETE | WatchMask::MOVED_FROM;
+    if recursive {
+        mask |= WatchMask::CREATE;
+    }
+
+    let inotify = Inotify::init().context("Creating Inotify instance")?;
+    let mut watches = inotify.watches();
+    let folders = Folders::default();
+    let root = watches.add(&dir, mask).context("Adding watcher")?;
+    folders.lock().unwrap().insert(root.clone(), PathBuf::new());
+    if recursive {
+        watch_subfolders(&mut watches, &folders, &dir, Path::new(""), &filter, mask)?;
+    }
+
+    let reader = Reader {
+        dir,
+        sender,
+        watches: watches.clone(),
+        folders: folders.clone(),
+        root: root.clone(),
+        mask,
+        recursive,
+        filter,
+        debounce,
+        recent: HashMap::new(),
+        scanned: HashSet::new(),
+    };
+    tokio::task::spawn_blocking(move || reader.run(inotify, backlog));
+
+    Ok(Watcher {
+        watches,
+        folders,
+        root,
+    })
+}
+
+/// Limit of debounced files to remember before forgetting the stale ones.
+const RECENT_LIMIT: usize = 1024;
+
+/// Reads events on blocking thread and sends paths of ready files, relative to the root folder.
+/// It stops once the root watch is removed or the notifier is dropped.
+struct Reader {
+    dir: PathBuf,
+    sender: Sender,
+    watches: Watches,
+    folders: Folders,
+    root: WatchDescriptor,
+    mask: WatchMask,
+    recursive: bool,
+    filter: Filter,
+    debounce: Option<Duration>,
+    recent: HashMap<PathBuf, Instant>,
+    /// Live events of scanned files are skipped until they are deleted or moved away.
+    scanned: HashSet<PathBuf>,
+}
+
+impl Reader {
+    fn run(mut self, mut inotify: Inotify, backlog: Backlog) {
+        let mut buffer = [0u8; 4096];
+
+        // Watches are added before scanning, so nothing is missed in between.
+        match scan(&self.dir, backlog, self.recursive, &self.filter) {
+            Ok(files) => {
+                for file in files {
+                    if !self.send(Ok(file.clone())) {
+                        return;
+                    }
+                    self.scanned.insert(file);
+                }
+            }
+            Err(why) => {
+                if !self.send(Err(Error::Scan(why))) {
+                    return;
+                }
+            }
+        }
+
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(why) => {
+                    self.send(Err(Error::Read(why)));
+                    return;
+                }
+            };
+
+            for event in events {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    if !self.send(Err(Error::Overflow)) {
+                        return;
+                    }
+                    continue;
+                }
+
+                if event.mask.contains(EventMask::IGNORED) {
+                    if event.wd == self.root {
+                        trace!("watcher is removed, stop reading events");
+                        self.sender.removed(&self.dir);
+                        return;
+                    }
+                    self.folders.lock().unwrap().remove(&event.wd);
+                    continue;
+                }
+
+                let folder = self.folders.lock().unwrap().get(&event.wd).cloned();
+                let (Some(folder), Some(name)) = (folder, event.name) else {
+                    continue;
+                };
+                let path = folder.join(name);
+
+                if event.mask.contains(EventMask::ISDIR) {
+                    if !self.recursive || self.filter.is_ignored(&path) {
+                        continue;
+                    }
+                    if event.mask.contains(EventMask::MOVED_FROM) {
+                        self.unwatch(&path);
+                    } else if event
+                        .mask
+                        .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                        && !self.watch(&path)
+                    {
+                        return;
+                    }
+                    continue;
+                }
+
+                if event
+                    .mask
+                    .intersects(EventMask::DELETE | EventMask::MOVED_FROM)
+                {
+                    self.scanned.remove(&path);
+                    continue;
+                }
+
+                if self.scanned.contains(&path) {
+                    trace!(file = ?path, "skipping event of scanned file");
+                    continue;
+                }
+
+                // Files are reported once they are completely written or moved in.
+                if event
+                    .mask
+                    .intersects(EventMask::CLOSE_WRITE | EventMask::MOVED_TO)
+                    && !self.report(path)
+                {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn report(&mut self, file: PathBuf) -> bool {
+        if !self.filter.is_match(&file) {
+            trace!(file = ?file, "skipping filtered file");
+            return true;
+        }
+
+        if let Some(period) = self.debounce {
+            let now = Instant::now();
+            if self.recent.len() > RECENT_LIMIT {
+                self.recent
+                    .retain(|_, last| now.duration_since(*last) < period);
+            }
+            if let Some(last) = self.recent.insert(file.clone(), now) {
+                if now.duration_since(last) < period {
+                    trace!(file = ?file, "skipping repeated event");
+                    return true;
+                }
+            }
+        }
+
+        self.send(Ok(file))
+    }
+
+    /// Watches new subfolder and reports files which got there before the watch was added,
+    /// e.g. when the subfolder is moved in along with its files.
+    fn watch(&mut self, folder: &Path) -> bool {
+        let files = watch_folder(
+            &mut self.watches,
+            &self.folders,
+            &self.dir,
+            folder,
+            &self.filter,
+            self.mask,
+        )
+        .and_then(|()| list_files(&self.dir, folder, true, &self.filter));
+
+        match files {
+            Ok(files) => {
+                for (file, _) in files {
+                    if !self.send(Ok(file.clone())) {
+                        return false;
+                    }
+                    self.scanned.insert(file);
+                }
+                true
+            }
+            // Subfolder may be gone already, it should not stop watching the others.
+            Err(why) => {
+                error!("failed watching subfolder: {:?}", why);
+                true
+            }
+        }
+    }
+
+    /// Stops watching subfolder which is moved away, its watches would report stale paths.
+    fn unwatch(&mut self, folder: &Path) {
+        let moved = self
+            .folders
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, path)| path.starts_with(folder))
+            .map(|(wd, _)| wd.clone())
+            .collect::<Vec<_>>();
+        for wd in moved {
+            if let Err(why) = self.watches.remove(wd) {
+                trace!("failed removing watcher of subfolder: {}", why);
+            }
+        }
+    }
+
+    fn send(&self, item: Result<PathBuf, Error>) -> bool {
+        self.sender.send(item)
+    }
+}
+
+fn watch_folder(
+    watches: &mut Watches,
+    folders: &Folders,
+    dir: &Path,
+    folder: &Path,
+    filter: &Filter,
+    mask: WatchMask,
+) -> Result<()> {
+    let wd = watches
+        .add(dir.join(folder), mask)
+        .context("Adding watcher of subfolder")?;
+    folders.lock().unwrap().insert(wd, folder.to_path_buf());
+    trace!(folder = ?folder, "subfolder watched");
+    watch_subfolders(watches, folders, dir, folder, filter, mask)
+}
+
+fn watch_subfolders(
+    watches: &mut Watches,
+    folders: &Folders,
+    dir: &Path,
+    folder: &Path,
+    filter: &Filter,
+    mask: WatchMask,
+) -> Result<()> {
+    for entry in fs::read_dir(dir.join(folder)).context("Reading folder")? {
+        let entry = entry.context("Reading folder entry")?;
+        let subfolder = folder.join(entry.file_name());
+        if entry.file_type().context("Getting file type")?.is_dir()
+            && !filter.is_ignored(&subfolder)
+        {
+            watch_folder(watches, folders, dir, &subfolder, filter, mask)?;
+        }
+    }
+    Ok(())
+}
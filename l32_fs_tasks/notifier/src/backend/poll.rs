@@ -0,0 +1,292 @@
+use crate::{
+    error::Error,
+    notifier::{list_files, pending, Backlog, Options, Sender},
+};
+use anyhow::{Context as _, Result};
+use std::{
+    collections::HashMap,
+    fs::DirEntry,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+use tracing::trace;
+
+/// Modification time and size of a file, a change of either means the file is rewritten.
+type Stamp = (Option<SystemTime>, u64);
+
+pub(crate) fn watch(
+    options: Options,
+    backlog: Backlog,
+    sender: Sender,
+    interval: Duration,
+) -> Result<()> {
+    // Folder is listed on the blocking thread, here it's only checked to fail early.
+    std::fs::read_dir(&options.dir).context("Listing folder")?;
+
+    let poller = Poller {
+        options,
+        sender,
+        interval,
+        known: HashMap::new(),
+        pending: HashMap::new(),
+    };
+    tokio::task::spawn_blocking(move || poller.run(backlog));
+
+    Ok(())
+}
+
+/// Lists the folder on blocking thread and sends paths of ready files, relative to the root folder.
+/// It stops at the next tick once the folder is removed or the notifier is closed.
+struct Poller {
+    options: Options,
+    sender: Sender,
+    interval: Duration,
+    /// Files which are reported or listed at start.
+    known: HashMap<PathBuf, Stamp>,
+    /// Files which are new or changed since the previous listing.
+    pending: HashMap<PathBuf, Stamp>,
+}
+
+impl Poller {
+    fn run(mut self, backlog: Backlog) {
+        let Options {
+            dir,
+            recursive,
+            filter,
+            ..
+        } = &self.options;
+
+        // Backlog is taken from the same listing as known files,
+        // so files written in between are reported only once.
+        let scanned = list_files(dir, Path::new(""), *recursive, filter)
+            .context("Listing folder")
+            .and_then(|files| {
+                self.known = stamps(&files);
+                pending(dir, files, backlog)
+            });
+
+        match scanned {
+            Ok(files) => {
+                for file in files {
+                    if !self.sender.send(Ok(file)) {
+                        return;
+                    }
+                }
+            }
+            Err(why) => {
+                if !self.sender.send(Err(Error::Scan(why))) {
+                    return;
+                }
+            }
+        }
+
+        loop {
+            thread::sleep(self.interval);
+
+            if self.sender.is_closed() {
+                trace!("notifier is closed, stop polling");
+                return;
+            }
+
+            if !self.options.dir.is_dir() {
+                trace!("folder is removed, stop polling");
+                self.sender.removed(&self.options.dir);
+                return;
+            }
+
+            let snapshot = match snapshot(&self.options) {
+                Ok(snapshot) => snapshot,
+                Err(why) => {
+                    if !self.sender.send(Err(Error::Scan(why))) {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            if !self.compare(snapshot) {
+                return;
+            }
+        }
+    }
+
+    /// Reports files which stayed unchanged since they were noticed,
+    /// so files which are still being written are skipped.
+    fn compare(&mut self, snapshot: HashMap<PathBuf, Stamp>) -> bool {
+        let mut ready = Vec::new();
+        for (file, stamp) in &snapshot {
+            if self.known.get(file) == Some(stamp) {
+                continue;
+            }
+            if self.pending.get(file) == Some(stamp) {
+                ready.push((file.clone(), *stamp));
+            } else {
+                self.pending.insert(file.clone(), *stamp);
+            }
+        }
+
+        self.known.retain(|file, _| snapshot.contains_key(file));
+        self.pending.retain(|file, _| snapshot.contains_key(file));
+
+        ready.sort();
+        for (file, stamp) in ready {
+            self.pending.remove(&file);
+            self.known.insert(file.clone(), stamp);
+            if !self.sender.send(Ok(file)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn snapshot(options: &Options) -> Result<HashMap<PathBuf, Stamp>> {
+    let files = list_files(
+        &options.dir,
+        Path::new(""),
+        options.recursive,
+        &options.filter,
+    )?;
+    Ok(stamps(&files))
+}
+
+fn stamps(files: &[(PathBuf, DirEntry)]) -> HashMap<PathBuf, Stamp> {
+    let mut stamps = HashMap::with_capacity(files.len());
+    for (file, entry) in files {
+        // File may be gone since listing.
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        stamps.insert(file.clone(), (metadata.modified().ok(), metadata.len()));
+    }
+    stamps
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, Backlog, Error, Notifier};
+    use futures::StreamExt;
+    use std::{fs, time::Duration};
+    use tempfile::TempDir;
+    use tokio::time;
+
+    const INTERVAL: Duration = Duration::from_millis(20);
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn watch(dir: &TempDir, backlog: Backlog) -> Notifier {
+        Notifier::builder(dir.path())
+            .backend(Backend::Poll(INTERVAL))
+            .backlog(backlog)
+            .recursive(true)
+            .extension("json")
+            .watch()
+            .await
+            .unwrap()
+    }
+
+    async fn next(notifier: &mut Notifier) -> Option<Result<String, Error>> {
+        let item = time::timeout(TIMEOUT, notifier.next())
+            .await
+            .expect("nothing is reported in time")?;
+        let dir = notifier.dir().to_path_buf();
+        Some(item.map(|file| {
+            let file = file.strip_prefix(dir).unwrap();
+            file.to_string_lossy().into_owned()
+        }))
+    }
+
+    async fn assert_silent(notifier: &mut Notifier) {
+        if let Ok(item) = time::timeout(INTERVAL * 10, notifier.next()).await {
+            panic!("unexpected {:?}", item);
+        }
+    }
+
+    #[tokio::test]
+    async fn backlog_and_new_files_are_reported_once() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("b.json"), "{}").unwrap();
+        fs::write(dir.path().join("a.json"), "{}").unwrap();
+        let mut notifier = watch(&dir, Backlog::ByName).await;
+
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/c.json"), "{}").unwrap();
+        fs::write(dir.path().join("c.txt"), "").unwrap();
+
+        for file in ["a.json", "b.json", "sub/c.json"] {
+            assert_eq!(next(&mut notifier).await.unwrap().unwrap(), file);
+        }
+        assert_silent(&mut notifier).await;
+    }
+
+    #[tokio::test]
+    async fn rewritten_file_is_reported_again() {
+        let dir = TempDir::new().unwrap();
+        let mut notifier = watch(&dir, Backlog::Skip).await;
+        fs::write(dir.path().join("a.json"), "{}").unwrap();
+        assert_eq!(next(&mut notifier).await.unwrap().unwrap(), "a.json");
+
+        fs::write(dir.path().join("a.json"), "{\"changed\": true}").unwrap();
+
+        assert_eq!(next(&mut notifier).await.unwrap().unwrap(), "a.json");
+        assert_silent(&mut notifier).await;
+    }
+
+    #[tokio::test]
+    async fn file_is_reported_once_it_stays_unchanged() {
+        let dir = TempDir::new().unwrap();
+        let interval = Duration::from_millis(200);
+        let mut notifier = Notifier::builder(dir.path())
+            .backend(Backend::Poll(interval))
+            .watch()
+            .await
+            .unwrap();
+        let path = dir.path().join("a.json");
+
+        // File which grows between listings is still being written.
+        for size in 1..=40 {
+            fs::write(&path, "x".repeat(size)).unwrap();
+            let res = time::timeout(interval / 10, notifier.next()).await;
+            assert!(res.is_err(), "reported while written: {:?}", res);
+        }
+
+        assert_eq!(next(&mut notifier).await.unwrap().unwrap(), "a.json");
+    }
+
+    #[tokio::test]
+    async fn closed_poller_ends_the_stream() {
+        let dir = TempDir::new().unwrap();
+        let mut notifier = watch(&dir, Backlog::Skip).await;
+
+        notifier.close().unwrap();
+
+        assert!(next(&mut notifier).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn removed_folder_is_reported_and_ends_the_stream() {
+        let dir = TempDir::new().unwrap();
+        let mut notifier = watch(&dir, Backlog::Skip).await;
+
+        fs::remove_dir(dir.path()).unwrap();
+
+        assert!(matches!(
+            next(&mut notifier).await,
+            Some(Err(Error::WatchRemoved(ref path))) if path == dir.path()
+        ));
+        assert!(next(&mut notifier).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn missing_folder_fails_early() {
+        let dir = TempDir::new().unwrap();
+
+        let res = Notifier::builder(dir.path().join("missing"))
+            .backend(Backend::Poll(INTERVAL))
+            .watch()
+            .await;
+
+        assert!(res.is_err());
+    }
+}
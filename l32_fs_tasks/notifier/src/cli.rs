@@ -0,0 +1,73 @@
+use crate::{Backend, Backlog, Consume};
+use clap::ValueEnum;
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum BacklogOrder {
+    /// Ignore existing files
+    Skip,
+    /// By file name
+    Name,
+    /// By modification time
+    Mtime,
+    /// By creation time of task
+    CreatedAt,
+}
+
+impl BacklogOrder {
+    /// Creation time is read from files by the key, only the caller knows what the files keep.
+    pub fn backlog(
+        self,
+        created_at: impl Fn(&Path) -> Option<SystemTime> + Send + 'static,
+    ) -> Backlog {
+        match self {
+            BacklogOrder::Skip => Backlog::Skip,
+            BacklogOrder::Name => Backlog::ByName,
+            BacklogOrder::Mtime => Backlog::ByMtime,
+            BacklogOrder::CreatedAt => Backlog::ByKey(Box::new(created_at)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OnConsumed {
+    /// Leave files in place
+    Keep,
+    /// Move files into `processed` subfolder
+    Archive,
+    /// Remove files
+    Delete,
+    /// Leave files in place and record them into `.consumed` journal
+    Journal,
+}
+
+impl From<OnConsumed> for Consume {
+    fn from(action: OnConsumed) -> Self {
+        match action {
+            OnConsumed::Keep => Consume::Keep,
+            OnConsumed::Archive => Consume::Archive,
+            OnConsumed::Delete => Consume::Delete,
+            OnConsumed::Journal => Consume::Journal,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Watcher {
+    /// Linux inotify events
+    Inotify,
+    /// Listing the folder at `--poll-interval`, for network, FUSE and overlay filesystems
+    Poll,
+}
+
+impl Watcher {
+    pub fn backend(self, poll_interval: Duration) -> Backend {
+        match self {
+            Watcher::Inotify => Backend::Inotify,
+            Watcher::Poll => Backend::Poll(poll_interval),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::{Context as _, Result};
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    io::ErrorKind,
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+};
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::trace;
+
+/// Subfolder of archived files.
+pub const ARCHIVE_DIR: &str = "processed";
+
+/// File with names of consumed files, one per line.
+/// Files of subfolders are recorded by relative paths.
+pub const JOURNAL_FILE: &str = ".consumed";
+
+/// What to do with a file of the watched folder once it is handled.
+#[derive(Debug, Clone, Copy)]
+pub enum Consume {
+    /// Leave the file in place.
+    Keep,
+    /// Move the file into [`ARCHIVE_DIR`] subfolder.
+    Archive,
+    /// Remove the file.
+    Delete,
+    /// Leave the file in place and record its name into [`JOURNAL_FILE`].
+    Journal,
+}
+
+/// Applies [`Consume`] action to handled files of the folder.
+pub struct Bookkeeper {
+    dir: PathBuf,
+    action: Consume,
+}
+
+impl Bookkeeper {
+    pub fn new(dir: impl Into<PathBuf>, action: Consume) -> Self {
+        Self {
+            dir: dir.into(),
+            action,
+        }
+    }
+
+    /// Folder of handled files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub async fn consume(&self, file: &Path) -> Result<()> {
+        // Files of subfolders are recorded by their paths relative to the folder.
+        let name = match file.strip_prefix(&self.dir) {
+            Ok(name) => name,
+            Err(_) => Path::new(file.file_name().context("Getting file name")?),
+        };
+
+        let res = match self.action {
+            Consume::Keep => return Ok(()),
+            Consume::Archive => {
+                let archived = self.dir.join(ARCHIVE_DIR).join(name);
+                if let Some(archive) = archived.parent() {
+                    fs::create_dir_all(archive)
+                        .await
+                        .context("Creating archive folder")?;
+                }
+                move_file(file, &archived)
+                    .await
+                    .context("Moving file into archive folder")
+            }
+            Consume::Delete => fs::remove_file(file).await.context("Removing file"),
+            Consume::Journal => {
+                let mut line = name.as_os_str().to_os_string().into_vec();
+                line.push(b'\n');
+
+                let mut journal = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.dir.join(JOURNAL_FILE))
+                    .await
+                    .context("Opening journal of consumed files")?;
+                journal
+                    .write_all(&line)
+                    .await
+                    .context("Writing journal of consumed files")
+            }
+        };
+
+        match res {
+            // File may be already moved away, e.g. into dead-letter folder.
+            Err(e) if is_not_found(&e) => Ok(()),
+            res => res.inspect(|_| {
+                trace!(
+                    file = file.to_string_lossy().as_ref(),
+                    action = ?self.action,
+                    "file consumed"
+                )
+            }),
+        }
+    }
+
+    /// Applies the action to the folder which belongs to the handled file, e.g. its attachments.
+    /// Folders are never reported, so they are left in place instead of journaling.
+    pub async fn consume_dir(&self, dir: &Path) -> Result<()> {
+        match self.action {
+            Consume::Keep | Consume::Journal => Ok(()),
+            Consume::Archive => self.consume(dir).await,
+            Consume::Delete => match fs::remove_dir_all(dir).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e).context("Removing folder"),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+/// Renames the file, or copies and removes it if the folders are on different file systems.
+pub async fn move_file(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to).await {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(e).context("Renaming file"),
+        Err(_) => {}
+    }
+
+    fs::copy(from, to).await.context("Copying file")?;
+    fs::remove_file(from).await.context("Removing file")
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::NotFound)
+}
+
+/// Names recorded into journal of the folder.
+pub(crate) fn journaled(dir: &Path) -> Result<HashSet<OsString>> {
+    match std::fs::read(dir.join(JOURNAL_FILE)) {
+        Ok(content) => Ok(content
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| OsString::from_vec(line.to_vec()))
+            .collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e).context("Reading journal of consumed files"),
+    }
+}
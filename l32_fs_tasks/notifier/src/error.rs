@@ -0,0 +1,23 @@
+use std::{io, path::PathBuf};
+use thiserror::Error;
+
+/// Errors reported by [`Notifier`](crate::Notifier) stream.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Kernel queue of events overflowed, files written meanwhile are not reported.
+    #[error("Queue of events overflowed, some events are lost")]
+    Overflow,
+
+    /// Watch of the folder is removed not by closing the notifier,
+    /// e.g. the folder is deleted or unmounted. The stream ends after it.
+    #[error("Watch of folder {0:?} is removed")]
+    WatchRemoved(PathBuf),
+
+    /// Reading events failed. The stream ends after it.
+    #[error("Failed reading events")]
+    Read(#[source] io::Error),
+
+    /// Listing files of the folder failed.
+    #[error(transparent)]
+    Scan(anyhow::Error),
+}
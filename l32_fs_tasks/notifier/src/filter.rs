@@ -0,0 +1,94 @@
+use anyhow::{Context as _, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+/// Glob patterns matched against paths relative to the watched folder and against file names,
+/// so `*.json` and `.*` work for files of subfolders as well.
+pub(crate) struct Filter {
+    include: Option<GlobSet>,
+    ignore: GlobSet,
+}
+
+impl Filter {
+    pub(crate) fn new(include: &[String], ignore: &[String]) -> Result<Self> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(glob_set(include).context("Building filter patterns")?)
+        };
+        let ignore = glob_set(ignore).context("Building ignore patterns")?;
+        Ok(Self { include, ignore })
+    }
+
+    /// Whether the file should be reported.
+    pub(crate) fn is_match(&self, file: &Path) -> bool {
+        let is_included = match &self.include {
+            Some(include) => matches(include, file),
+            None => true,
+        };
+        is_included && !self.is_ignored(file)
+    }
+
+    /// Whether the file or the subfolder is skipped entirely.
+    pub(crate) fn is_ignored(&self, path: &Path) -> bool {
+        matches(&self.ignore, path)
+    }
+}
+
+fn matches(set: &GlobSet, path: &Path) -> bool {
+    set.is_match(path) || path.file_name().is_some_and(|name| set.is_match(name))
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).with_context(|| format!("Parsing pattern {pattern:?}"))?);
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], ignore: &[&str]) -> Filter {
+        let strings =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        Filter::new(&strings(include), &strings(ignore)).unwrap()
+    }
+
+    #[test]
+    fn empty_filter_matches_every_file() {
+        let filter = filter(&[], &[]);
+
+        assert!(filter.is_match(Path::new("task.json")));
+        assert!(filter.is_match(Path::new("daily/task.cbor")));
+    }
+
+    #[test]
+    fn patterns_match_file_names_of_subfolders() {
+        let filter = filter(&["*.json"], &[".*"]);
+
+        assert!(filter.is_match(Path::new("task.json")));
+        assert!(filter.is_match(Path::new("daily/task.json")));
+        assert!(!filter.is_match(Path::new("task.cbor")));
+        assert!(!filter.is_match(Path::new(".task.json")));
+        assert!(!filter.is_match(Path::new("daily/.task.json")));
+    }
+
+    #[test]
+    fn ignored_subfolder_is_matched_by_name_or_path() {
+        let filter = filter(&[], &["processed", "daily/old"]);
+
+        assert!(filter.is_ignored(Path::new("processed")));
+        assert!(filter.is_ignored(Path::new("daily/processed")));
+        assert!(filter.is_ignored(Path::new("daily/old")));
+        assert!(!filter.is_ignored(Path::new("old")));
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        assert!(Filter::new(&["[".to_string()], &[]).is_err());
+        assert!(Filter::new(&[], &["[".to_string()]).is_err());
+    }
+}
@@ -1,3 +1,15 @@
+mod backend;
+/// Command line values of notifier options, shared by binaries which watch folders.
+#[cfg(feature = "clap")]
+pub mod cli;
+mod consumed;
+mod error;
+mod filter;
 mod notifier;
+mod telemetry;
 
+pub use backend::Backend;
+pub use consumed::*;
+pub use error::Error;
 pub use notifier::*;
+pub use telemetry::describe_metrics;
@@ -1,94 +1,615 @@
-use anyhow::{anyhow, Context as _, Result};
+use crate::{
+    backend::{self, Backend},
+    consumed::journaled,
+    error::Error,
+    filter::Filter,
+    telemetry,
+};
+use anyhow::{Context as _, Result};
 use futures::Stream;
-use inotify::{EventMask, Inotify, WatchMask};
 use std::{
-    ffi::{OsStr, OsString},
-    future::Future,
+    fs::{self, DirEntry},
     path::{Path, PathBuf},
     pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
+    time::{Duration, SystemTime},
 };
-use tokio::{
-    pin,
-    sync::mpsc::{self, UnboundedReceiver},
-};
-use tracing::error;
-
-pub struct Notifier<'a> {
-    dir: &'a Path,
-    rx: UnboundedReceiver<Option<Result<OsString>>>,
-}
-
-pub async fn try_watch(dir: &Path) -> Result<Notifier<'_>> {
-    let mut inotify = {
-        let inotify = Inotify::init().context("Creating Inotify instance")?;
-        inotify
-            .watches()
-            .add(dir, WatchMask::MODIFY)
-            .context("Adding watcher")?;
-        inotify
-    };
-
-    let (tx, rx) = mpsc::unbounded_channel();
-
-    tokio::task::spawn_blocking(move || {
-        let mut buffer = [0u8; 4096];
-
-        loop {
-            let events = match inotify
-                .read_events_blocking(&mut buffer)
-                .inspect_err(|why| error!("failed reading events: {}", why))
-            {
-                Ok(events) => events,
-                _ => {
-                    break tx
-                        .send(None)
-                        .inspect_err(|why| error!("failed sending to worker: {:?}", why))
-                }
-            };
-
-            for event in events {
-                let is_modified_file = event.mask.contains(EventMask::MODIFY)
-                    && !event.mask.contains(EventMask::ISDIR);
-
-                if is_modified_file {
-                    let res = event
-                        .name
-                        .map(OsStr::to_os_string)
-                        .ok_or(anyhow!("failed to get file name"));
-
-                    if tx
-                        .send(Some(res))
-                        .inspect_err(|why| error!("failed sending to worker: {:?}", why))
-                        .is_err()
-                    {
-                        break;
-                    }
-                }
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{error, trace};
+
+/// Stream of files ready in the folder, paths include the folder.
+/// Dropping the notifier closes it.
+pub struct Notifier {
+    dir: PathBuf,
+    rx: UnboundedReceiver<Result<PathBuf, Error>>,
+    handle: Handle,
+    closed: Arc<AtomicBool>,
+}
+
+enum Handle {
+    Inotify(backend::inotify::Watcher),
+    Poll,
+}
+
+impl Notifier {
+    pub fn builder(dir: impl Into<PathBuf>) -> Builder {
+        Builder::new(dir)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Stops the backend, so the stream ends after events which are already read.
+    pub fn close(&mut self) -> Result<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        match &mut self.handle {
+            Handle::Inotify(watcher) => watcher.close(),
+            // Poller stops at the next tick once the channel is closed.
+            Handle::Poll => {
+                self.rx.close();
+                trace!("poller closed");
+                Ok(())
             }
         }
-    });
+    }
+}
 
-    Ok(Notifier { dir, rx })
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        if let Err(why) = self.close() {
+            trace!("failed closing notifier on drop: {:?}", why);
+        }
+    }
 }
 
-impl<'a> Stream for Notifier<'a> {
-    type Item = Result<PathBuf>;
+/// Files which already exist in the folder when watching starts.
+/// Files recorded into journal of consumed files are never reported.
+pub enum Backlog {
+    /// Report only files written after start.
+    Skip,
+    /// Report existing files in order of their names.
+    ByName,
+    /// Report existing files starting from the least recently modified one.
+    ByMtime,
+    /// Report existing files ordered by the key, files without key go last.
+    ByKey(BacklogKey),
+}
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let dir = *unsafe { self.as_mut().map_unchecked_mut(|this| &mut this.dir) };
+pub type BacklogKey = Box<dyn Fn(&Path) -> Option<SystemTime> + Send>;
 
-        let recv = self.get_mut().rx.recv();
-        pin!(recv);
+/// Configures which files of the folder are reported.
+pub struct Builder {
+    dir: PathBuf,
+    backlog: Backlog,
+    backend: Backend,
+    recursive: bool,
+    filters: Vec<String>,
+    ignores: Vec<String>,
+    debounce: Option<Duration>,
+}
+
+impl Builder {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            backlog: Backlog::Skip,
+            backend: Backend::default(),
+            recursive: false,
+            filters: Vec::new(),
+            ignores: Vec::new(),
+            debounce: None,
+        }
+    }
+
+    pub fn backlog(mut self, backlog: Backlog) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Watch subfolders as well, including ones created later.
+    /// Reported paths keep subfolders, e.g. `input/daily/task.json`.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Report only files matching any of glob patterns, e.g. `*.json`.
+    pub fn filter(mut self, pattern: impl Into<String>) -> Self {
+        self.filters.push(pattern.into());
+        self
+    }
+
+    /// Report only files with the extension.
+    pub fn extension(self, extension: &str) -> Self {
+        self.filter(format!("*.{extension}"))
+    }
+
+    /// Report only files with any of the extensions.
+    pub fn extensions<'a>(self, extensions: impl IntoIterator<Item = &'a str>) -> Self {
+        extensions
+            .into_iter()
+            .fold(self, |builder, extension| builder.extension(extension))
+    }
+
+    /// Skip files and subfolders matching the glob pattern, e.g. `.*` for temporary files.
+    pub fn ignore(mut self, pattern: impl Into<String>) -> Self {
+        self.ignores.push(pattern.into());
+        self
+    }
+
+    /// Report a file once while its events keep coming within the period.
+    /// Polling reports a file once per change anyway.
+    pub fn debounce(mut self, period: Duration) -> Self {
+        self.debounce = Some(period);
+        self
+    }
 
-        match recv.poll(cx) {
-            Poll::Ready(Some(Some(try_str))) => {
-                let try_path = try_str.map(|str| Path::new(dir).join(str));
-                let res = Some(try_path);
-                Poll::Ready(res)
+    pub async fn watch(self) -> Result<Notifier> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let options = Options {
+            dir: self.dir.clone(),
+            recursive: self.recursive,
+            filter: Filter::new(&self.filters, &self.ignores)?,
+            debounce: self.debounce,
+        };
+        let sender = Sender {
+            tx,
+            closed: closed.clone(),
+        };
+
+        // Adding watches walks subfolders, so backends start on blocking thread.
+        let (backend, backlog) = (self.backend, self.backlog);
+        let handle = tokio::task::spawn_blocking(move || match backend {
+            Backend::Inotify => {
+                backend::inotify::watch(options, backlog, sender).map(Handle::Inotify)
+            }
+            Backend::Poll(interval) => {
+                backend::poll::watch(options, backlog, sender, interval).map(|()| Handle::Poll)
             }
-            Poll::Pending => Poll::Pending,
-            _ => Poll::Ready(None),
+        })
+        .await
+        .context("Waiting for watch to start")??;
+
+        Ok(Notifier {
+            dir: self.dir,
+            rx,
+            handle,
+            closed,
+        })
+    }
+}
+
+pub async fn try_watch(dir: impl Into<PathBuf>, backlog: Backlog) -> Result<Notifier> {
+    Builder::new(dir).backlog(backlog).watch().await
+}
+
+/// Settings shared by backends.
+pub(crate) struct Options {
+    pub(crate) dir: PathBuf,
+    pub(crate) recursive: bool,
+    pub(crate) filter: Filter,
+    pub(crate) debounce: Option<Duration>,
+}
+
+/// Sends paths of ready files, relative to the root folder, to the notifier.
+pub(crate) struct Sender {
+    tx: UnboundedSender<Result<PathBuf, Error>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Sender {
+    pub(crate) fn send(&self, item: Result<PathBuf, Error>) -> bool {
+        self.tx
+            .send(item)
+            .inspect_err(|why| {
+                // Receiver is gone along with dropped notifier.
+                if self.closed.load(Ordering::SeqCst) {
+                    trace!("notifier is closed, stop reading events");
+                } else {
+                    error!("failed sending to worker: {:?}", why);
+                }
+            })
+            .is_ok()
+    }
+
+    /// Whether the notifier is closed or dropped.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Reports that the folder is gone, the backend stops after it.
+    pub(crate) fn removed(&self, dir: &Path) {
+        // Removed watch needs no closing.
+        if !self.closed.swap(true, Ordering::SeqCst) {
+            self.send(Err(Error::WatchRemoved(dir.to_path_buf())));
+        }
+    }
+}
+
+/// Files of the folder matching the filter, paths are relative to the root folder.
+pub(crate) fn list_files(
+    dir: &Path,
+    folder: &Path,
+    recursive: bool,
+    filter: &Filter,
+) -> Result<Vec<(PathBuf, DirEntry)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir.join(folder)).context("Reading folder")? {
+        let entry = entry.context("Reading folder entry")?;
+        let path = folder.join(entry.file_name());
+        let file_type = entry.file_type().context("Getting file type")?;
+        if file_type.is_file() && filter.is_match(&path) {
+            files.push((path, entry));
+        } else if recursive && file_type.is_dir() && !filter.is_ignored(&path) {
+            files.extend(list_files(dir, &path, recursive, filter)?);
         }
     }
+    Ok(files)
+}
+
+pub(crate) fn scan(
+    dir: &Path,
+    backlog: Backlog,
+    recursive: bool,
+    filter: &Filter,
+) -> Result<Vec<PathBuf>> {
+    if let Backlog::Skip = backlog {
+        return Ok(Vec::new());
+    }
+
+    let files = list_files(dir, Path::new(""), recursive, filter)?;
+    pending(dir, files, backlog)
+}
+
+/// Files of the listing which are left to handle, in order of the backlog.
+pub(crate) fn pending(
+    dir: &Path,
+    mut files: Vec<(PathBuf, DirEntry)>,
+    backlog: Backlog,
+) -> Result<Vec<PathBuf>> {
+    if let Backlog::Skip = backlog {
+        return Ok(Vec::new());
+    }
+
+    let consumed = journaled(dir)?;
+
+    files.retain(|(file, _)| !consumed.contains(file.as_os_str()));
+
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    match backlog {
+        Backlog::ByMtime => files.sort_by_cached_key(|(_, entry)| {
+            entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        }),
+        Backlog::ByKey(key) => files.sort_by_cached_key(|(_, entry)| {
+            let key = key(&entry.path());
+            (key.is_none(), key)
+        }),
+        _ => {}
+    }
+
+    trace!(files = files.len(), "folder scanned");
+
+    Ok(files.into_iter().map(|(file, _)| file).collect())
+}
+
+impl Stream for Notifier {
+    type Item = Result<PathBuf, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        this.rx.poll_recv(cx).map(|item| {
+            item.inspect(telemetry::record)
+                .map(|try_file| try_file.map(|file| this.dir.join(file)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JOURNAL_FILE;
+    use futures::StreamExt;
+    use std::fs::File;
+    use tempfile::TempDir;
+    use tokio::time;
+
+    /// Events of a file arrive well within the timeout, silence is awaited for less.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const SILENCE: Duration = Duration::from_millis(300);
+
+    fn builder(dir: &TempDir) -> Builder {
+        Notifier::builder(dir.path()).extension("json").ignore(".*")
+    }
+
+    async fn next(notifier: &mut Notifier) -> PathBuf {
+        let file = time::timeout(TIMEOUT, notifier.next())
+            .await
+            .expect("file is not reported in time")
+            .expect("notifier is closed")
+            .unwrap();
+        file.strip_prefix(notifier.dir()).unwrap().to_path_buf()
+    }
+
+    async fn assert_silent(notifier: &mut Notifier) {
+        if let Ok(item) = time::timeout(SILENCE, notifier.next()).await {
+            panic!("unexpected {:?}", item);
+        }
+    }
+
+    fn write(dir: &TempDir, file: &str) {
+        let path = dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "{}").unwrap();
+    }
+
+    #[tokio::test]
+    async fn backlog_by_name_is_reported_before_live_files() {
+        let dir = TempDir::new().unwrap();
+        for file in ["b.json", "a.json", "c.json"] {
+            write(&dir, file);
+        }
+
+        let mut notifier = builder(&dir)
+            .backlog(Backlog::ByName)
+            .watch()
+            .await
+            .unwrap();
+        write(&dir, "0.json");
+
+        for file in ["a.json", "b.json", "c.json", "0.json"] {
+            assert_eq!(next(&mut notifier).await, Path::new(file));
+        }
+    }
+
+    #[tokio::test]
+    async fn backlog_by_mtime_starts_from_least_recently_modified() {
+        let dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        for (file, age) in [("a.json", 10), ("b.json", 30), ("c.json", 20)] {
+            write(&dir, file);
+            File::options()
+                .write(true)
+                .open(dir.path().join(file))
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+        }
+
+        let mut notifier = builder(&dir)
+            .backlog(Backlog::ByMtime)
+            .watch()
+            .await
+            .unwrap();
+
+        for file in ["b.json", "c.json", "a.json"] {
+            assert_eq!(next(&mut notifier).await, Path::new(file));
+        }
+    }
+
+    #[tokio::test]
+    async fn backlog_by_key_puts_files_without_key_last() {
+        let dir = TempDir::new().unwrap();
+        for file in ["a.json", "b.json", "c.json"] {
+            write(&dir, file);
+        }
+        let key = |path: &Path| match path.file_name()?.to_str()? {
+            "a.json" => Some(SystemTime::UNIX_EPOCH + Duration::from_secs(2)),
+            "c.json" => Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1)),
+            _ => None,
+        };
+
+        let mut notifier = builder(&dir)
+            .backlog(Backlog::ByKey(Box::new(key)))
+            .watch()
+            .await
+            .unwrap();
+
+        for file in ["c.json", "a.json", "b.json"] {
+            assert_eq!(next(&mut notifier).await, Path::new(file));
+        }
+    }
+
+    #[tokio::test]
+    async fn skipped_backlog_reports_only_new_files() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "old.json");
+
+        let mut notifier = builder(&dir).watch().await.unwrap();
+        write(&dir, "new.json");
+
+        assert_eq!(next(&mut notifier).await, Path::new("new.json"));
+        assert_silent(&mut notifier).await;
+    }
+
+    #[tokio::test]
+    async fn journaled_files_are_not_in_backlog() {
+        let dir = TempDir::new().unwrap();
+        for file in ["a.json", "b.json", "sub/c.json"] {
+            write(&dir, file);
+        }
+        fs::write(dir.path().join(JOURNAL_FILE), "a.json\nsub/c.json\n").unwrap();
+
+        let mut notifier = builder(&dir)
+            .backlog(Backlog::ByName)
+            .recursive(true)
+            .watch()
+            .await
+            .unwrap();
+
+        assert_eq!(next(&mut notifier).await, Path::new("b.json"));
+        assert_silent(&mut notifier).await;
+    }
+
+    #[tokio::test]
+    async fn scanned_file_is_not_reported_again_until_it_is_removed() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "a.json");
+
+        let mut notifier = builder(&dir)
+            .backlog(Backlog::ByName)
+            .watch()
+            .await
+            .unwrap();
+        assert_eq!(next(&mut notifier).await, Path::new("a.json"));
+
+        // Events of the file written before the scan may come after it.
+        write(&dir, "a.json");
+        write(&dir, "b.json");
+        assert_eq!(next(&mut notifier).await, Path::new("b.json"));
+
+        fs::remove_file(dir.path().join("a.json")).unwrap();
+        write(&dir, "a.json");
+        assert_eq!(next(&mut notifier).await, Path::new("a.json"));
+    }
+
+    #[tokio::test]
+    async fn moved_in_file_is_reported() {
+        let dir = TempDir::new().unwrap();
+        let mut notifier = builder(&dir).watch().await.unwrap();
+
+        write(&dir, ".a.json.tmp");
+        fs::rename(dir.path().join(".a.json.tmp"), dir.path().join("a.json")).unwrap();
+
+        assert_eq!(next(&mut notifier).await, Path::new("a.json"));
+        assert_silent(&mut notifier).await;
+    }
+
+    #[tokio::test]
+    async fn debounce_reports_repeated_writes_once() {
+        let dir = TempDir::new().unwrap();
+        let mut notifier = builder(&dir)
+            .debounce(Duration::from_secs(60))
+            .watch()
+            .await
+            .unwrap();
+
+        write(&dir, "a.json");
+        write(&dir, "a.json");
+        write(&dir, "b.json");
+
+        assert_eq!(next(&mut notifier).await, Path::new("a.json"));
+        assert_eq!(next(&mut notifier).await, Path::new("b.json"));
+        assert_silent(&mut notifier).await;
+    }
+
+    #[tokio::test]
+    async fn filtered_and_ignored_files_are_skipped() {
+        let dir = TempDir::new().unwrap();
+        let mut notifier = builder(&dir).watch().await.unwrap();
+
+        write(&dir, "a.txt");
+        write(&dir, ".a.json");
+        write(&dir, "a.json");
+
+        assert_eq!(next(&mut notifier).await, Path::new("a.json"));
+        assert_silent(&mut notifier).await;
+    }
+
+    #[tokio::test]
+    async fn subfolders_are_not_watched_unless_recursive() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "sub/a.json");
+        let mut notifier = builder(&dir)
+            .backlog(Backlog::ByName)
+            .watch()
+            .await
+            .unwrap();
+
+        write(&dir, "sub/b.json");
+        write(&dir, "c.json");
+
+        assert_eq!(next(&mut notifier).await, Path::new("c.json"));
+        assert_silent(&mut notifier).await;
+    }
+
+    #[tokio::test]
+    async fn recursive_watch_reports_files_of_subfolders() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "sub/a.json");
+        write(&dir, "skip/a.json");
+        let mut notifier = builder(&dir)
+            .backlog(Backlog::ByName)
+            .recursive(true)
+            .ignore("skip")
+            .watch()
+            .await
+            .unwrap();
+        assert_eq!(next(&mut notifier).await, Path::new("sub/a.json"));
+
+        write(&dir, "sub/deep/b.json");
+        assert_eq!(next(&mut notifier).await, Path::new("sub/deep/b.json"));
+
+        write(&dir, "skip/b.json");
+        assert_silent(&mut notifier).await;
+    }
+
+    #[tokio::test]
+    async fn files_of_moved_in_subfolder_are_reported() {
+        let dir = TempDir::new().unwrap();
+        let outside = TempDir::new_in(dir.path().parent().unwrap()).unwrap();
+        fs::create_dir(outside.path().join("sub")).unwrap();
+        fs::write(outside.path().join("sub/a.json"), "{}").unwrap();
+        let mut notifier = builder(&dir).recursive(true).watch().await.unwrap();
+
+        fs::rename(outside.path().join("sub"), dir.path().join("sub")).unwrap();
+
+        assert_eq!(next(&mut notifier).await, Path::new("sub/a.json"));
+        write(&dir, "sub/b.json");
+        assert_eq!(next(&mut notifier).await, Path::new("sub/b.json"));
+    }
+
+    #[tokio::test]
+    async fn closed_notifier_ends_the_stream() {
+        let dir = TempDir::new().unwrap();
+        let mut notifier = builder(&dir).watch().await.unwrap();
+
+        notifier.close().unwrap();
+        notifier.close().unwrap();
+
+        let item = time::timeout(TIMEOUT, notifier.next()).await.unwrap();
+        assert!(item.is_none());
+    }
+
+    #[tokio::test]
+    async fn removed_folder_is_reported_and_ends_the_stream() {
+        let dir = TempDir::new().unwrap();
+        let mut notifier = builder(&dir).watch().await.unwrap();
+
+        fs::remove_dir(dir.path()).unwrap();
+
+        let item = time::timeout(TIMEOUT, notifier.next()).await.unwrap();
+        assert!(matches!(item, Some(Err(Error::WatchRemoved(ref path))) if path == dir.path()));
+        let item = time::timeout(TIMEOUT, notifier.next()).await.unwrap();
+        assert!(item.is_none());
+    }
+
+    #[tokio::test]
+    async fn watch_fails_on_missing_folder_or_bad_pattern() {
+        let dir = TempDir::new().unwrap();
+
+        assert!(Notifier::builder(dir.path().join("missing"))
+            .watch()
+            .await
+            .is_err());
+        assert!(builder(&dir).filter("[").watch().await.is_err());
+    }
 }
@@ -0,0 +1,27 @@
+use crate::error::Error;
+use metrics::{counter, describe_counter};
+use std::path::PathBuf;
+
+pub const FILES_REPORTED: &str = "notifier_files_total";
+pub const ERRORS: &str = "notifier_errors_total";
+
+/// Describes metrics of the notifier for the installed recorder.
+pub fn describe_metrics() {
+    describe_counter!(FILES_REPORTED, "Files reported by the notifier.");
+    describe_counter!(ERRORS, "Errors reported by the notifier, labeled by kind.");
+}
+
+pub(crate) fn record(item: &Result<PathBuf, Error>) {
+    match item {
+        Ok(_) => counter!(FILES_REPORTED).increment(1),
+        Err(why) => {
+            let kind = match why {
+                Error::Overflow => "overflow",
+                Error::WatchRemoved(_) => "watch_removed",
+                Error::Read(_) => "read",
+                Error::Scan(_) => "scan",
+            };
+            counter!(ERRORS, "kind" => kind).increment(1);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use crate::{from_value, to_value, Schema};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::{borrow::Cow, path::Path};
+use uuid::Uuid;
+
+/// Frame of zstd starts with these bytes.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const ZSTD_EXTENSION: &str = "zst";
+
+/// Extensions of files of every codec, e.g. to watch the folder for them.
+pub const EXTENSIONS: [&str; 6] = [
+    "json",
+    "cbor",
+    "msgpack",
+    "json.zst",
+    "cbor.zst",
+    "msgpack.zst",
+];
+
+/// Format messages are written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Encoding {
+    /// Plain JSON, `.json`
+    #[default]
+    Json,
+    /// CBOR, `.cbor`
+    Cbor,
+    /// MessagePack, `.msgpack`
+    #[cfg_attr(feature = "clap", value(name = "msgpack"))]
+    MessagePack,
+}
+
+impl Encoding {
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::Cbor => "cbor",
+            Encoding::MessagePack => "msgpack",
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "json" => Some(Encoding::Json),
+            "cbor" => Some(Encoding::Cbor),
+            "msgpack" => Some(Encoding::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format by the first byte, messages are always maps.
+    fn from_content(content: &[u8]) -> Option<Self> {
+        match content.iter().find(|byte| !byte.is_ascii_whitespace())? {
+            b'{' => Some(Encoding::Json),
+            // Map of up to 15 items, map 16 and map 32.
+            0x80..=0x8f | 0xde | 0xdf => Some(Encoding::MessagePack),
+            // Map of any length and self-described CBOR tag.
+            0xa0..=0xbb | 0xbf | 0xd9 => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    fn encode(self, value: &Value) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value).context("Serializing JSON"),
+            Encoding::Cbor => {
+                let mut content = Vec::new();
+                ciborium::into_writer(value, &mut content).context("Serializing CBOR")?;
+                Ok(content)
+            }
+            Encoding::MessagePack => rmp_serde::to_vec(value).context("Serializing MessagePack"),
+        }
+    }
+
+    fn decode(self, content: &[u8]) -> Result<Value> {
+        match self {
+            Encoding::Json => serde_json::from_slice(content).context("Parsing JSON"),
+            Encoding::Cbor => ciborium::from_reader(content).context("Parsing CBOR"),
+            Encoding::MessagePack => rmp_serde::from_slice(content).context("Parsing MessagePack"),
+        }
+    }
+}
+
+/// How message files are written, readers detect it by extension or content of the file.
+/// Binary formats keep the same fields as JSON, so versions of schema are upgraded alike.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Codec {
+    pub encoding: Encoding,
+    /// Compress with zstd, e.g. for tasks with large descriptions.
+    pub zstd: bool,
+}
+
+impl Codec {
+    /// Codec of the file by its extension, e.g. `.cbor.zst`.
+    pub fn of_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let (name, zstd) = match name.strip_suffix(ZSTD_EXTENSION) {
+            Some(name) => (name.strip_suffix('.')?, true),
+            None => (name, false),
+        };
+        let (_, extension) = name.rsplit_once('.')?;
+        let encoding = Encoding::from_extension(extension)?;
+        Some(Self { encoding, zstd })
+    }
+
+    /// Extension of files without leading dot.
+    pub fn extension(&self) -> String {
+        if self.zstd {
+            format!("{}.{}", self.encoding.extension(), ZSTD_EXTENSION)
+        } else {
+            self.encoding.extension().to_owned()
+        }
+    }
+
+    /// Name of the file of the message.
+    pub fn file_name(&self, id: Uuid) -> String {
+        format!("{}.{}", id, self.extension())
+    }
+
+    pub fn encode<T: Schema>(&self, value: &T) -> Result<Vec<u8>> {
+        let content = self.encoding.encode(&to_value(value)?)?;
+        if self.zstd {
+            zstd::encode_all(content.as_slice(), 0).context("Compressing with zstd")
+        } else {
+            Ok(content)
+        }
+    }
+}
+
+/// Reads the message of any codec and schema version,
+/// the codec is detected by extension of the file, or by its content if extension is unknown.
+pub fn decode<T: Schema>(path: &Path, content: &[u8]) -> Result<T> {
+    let codec = Codec::of_path(path);
+
+    let zstd = codec.map_or_else(|| content.starts_with(&ZSTD_MAGIC), |codec| codec.zstd);
+    let content = if zstd {
+        Cow::Owned(zstd::decode_all(content).context("Decompressing with zstd")?)
+    } else {
+        Cow::Borrowed(content)
+    };
+
+    let encoding = codec
+        .map(|codec| codec.encoding)
+        .or_else(|| Encoding::from_content(&content))
+        .context("Unknown encoding")?;
+
+    from_value(encoding.decode(&content)?)
+}
+
+/// Id of the message by name of its file, e.g. `<id>.json.zst`.
+pub fn file_id(path: &Path) -> Option<Uuid> {
+    Codec::of_path(path)?;
+    let name = path.file_name()?.to_str()?;
+    let (id, _) = name.split_once('.')?;
+    id.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Task};
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    const ENCODINGS: [Encoding; 3] = [Encoding::Json, Encoding::Cbor, Encoding::MessagePack];
+
+    fn task() -> Task {
+        Task {
+            kind: "shell".to_string(),
+            args: vec!["echo hello".to_string()],
+            payload: json!({ "key": [1, 2, 3] }),
+            priority: -3,
+            depends_on: vec![Uuid::from_u128(2)],
+            ..test_util::task(1)
+        }
+    }
+
+    fn codecs() -> impl Iterator<Item = Codec> {
+        ENCODINGS.into_iter().flat_map(|encoding| {
+            [false, true]
+                .into_iter()
+                .map(move |zstd| Codec { encoding, zstd })
+        })
+    }
+
+    #[test]
+    fn of_path_detects_codec_by_extension() {
+        let cases = [
+            ("task.json", Some((Encoding::Json, false))),
+            ("tasks/task.cbor", Some((Encoding::Cbor, false))),
+            ("task.msgpack", Some((Encoding::MessagePack, false))),
+            ("task.json.zst", Some((Encoding::Json, true))),
+            ("task.cbor.zst", Some((Encoding::Cbor, true))),
+            ("task.msgpack.zst", Some((Encoding::MessagePack, true))),
+            ("task.zst", None),
+            ("task.txt", None),
+            ("task.json.gz", None),
+            ("json", None),
+        ];
+
+        for (path, expected) in cases {
+            let codec = Codec::of_path(Path::new(path)).map(|codec| (codec.encoding, codec.zstd));
+            assert_eq!(codec, expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn extensions_are_of_every_codec() {
+        let extensions = codecs().map(|codec| codec.extension()).collect::<Vec<_>>();
+        assert_eq!(extensions.len(), EXTENSIONS.len());
+        for extension in EXTENSIONS {
+            assert!(
+                extensions.iter().any(|known| known == extension),
+                "{}",
+                extension
+            );
+        }
+    }
+
+    #[test]
+    fn from_content_detects_encoding_by_first_byte() {
+        let value = json!({ "key": "value" });
+        for encoding in ENCODINGS {
+            let content = encoding.encode(&value).unwrap();
+            assert_eq!(Encoding::from_content(&content), Some(encoding));
+        }
+
+        assert_eq!(Encoding::from_content(b" \n\t{}"), Some(Encoding::Json));
+        assert_eq!(Encoding::from_content(b"[1, 2]"), None);
+        assert_eq!(Encoding::from_content(b"  "), None);
+    }
+
+    #[test]
+    fn round_trip_of_every_codec() {
+        let task = task();
+
+        for codec in codecs() {
+            let content = codec.encode(&task).unwrap();
+            assert_eq!(content.starts_with(&ZSTD_MAGIC), codec.zstd, "{:?}", codec);
+
+            let path = PathBuf::from(codec.file_name(task.id));
+            let decoded = decode::<Task>(&path, &content).unwrap();
+            assert_eq!(
+                to_value(&decoded).unwrap(),
+                to_value(&task).unwrap(),
+                "{:?}",
+                codec
+            );
+            assert_eq!(file_id(&path), Some(task.id));
+        }
+    }
+
+    #[test]
+    fn decode_detects_codec_by_content_of_unknown_extension() {
+        let task = task();
+
+        for codec in codecs() {
+            let content = codec.encode(&task).unwrap();
+            let decoded = decode::<Task>(Path::new("task.bin"), &content).unwrap();
+            assert_eq!(decoded.id, task.id, "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn decode_trusts_extension_over_content() {
+        let content = Codec::default().encode(&task()).unwrap();
+        assert!(decode::<Task>(Path::new("task.cbor"), &content).is_err());
+    }
+
+    #[test]
+    fn decode_fails_on_unknown_content() {
+        assert!(decode::<Task>(Path::new("task.bin"), b"garbage").is_err());
+        assert!(decode::<Task>(Path::new("task.bin"), &ZSTD_MAGIC).is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+use tracing::trace;
+use uuid::Uuid;
+
+/// Period and counter of rotated file, e.g. `("2024-10-17", 1)` of `processed-tasks.log.2024-10-17.1.gz`.
+/// Periods sort in order of time, unlike modification times of files rotated at once.
+pub fn rotation_order(path: &Path, prefix: &str) -> (String, u32) {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let suffix = name.strip_prefix(prefix).unwrap_or(&name);
+    let suffix = suffix.strip_suffix(".gz").unwrap_or(suffix);
+
+    match suffix.split_once('.') {
+        Some((period, counter)) => (period.to_string(), counter.parse().unwrap_or_default()),
+        None => (suffix.to_string(), 0),
+    }
+}
+
+/// Rotated files of the journal, plain or compressed, from the oldest one.
+pub fn rotated_files(dir: &Path, file: &Path) -> Result<Vec<PathBuf>> {
+    let prefix = format!("{}.", file.to_string_lossy());
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).context("Reading journal folder")? {
+        let entry = entry.context("Reading journal folder entry")?;
+        if entry.file_name().to_string_lossy().starts_with(&prefix)
+            && entry.file_type().context("Getting file type")?.is_file()
+        {
+            files.push((rotation_order(&entry.path(), &prefix), entry.path()));
+        }
+    }
+    files.sort();
+
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Rotated files of the journal from the oldest one and the live file the last.
+pub fn journal_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let (Some(dir), Some(file)) = (path.parent(), path.file_name()) else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+
+    let mut files = match rotated_files(dir, Path::new(file)) {
+        Ok(files) => files,
+        // Journal is not started yet.
+        Err(why)
+            if why
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == ErrorKind::NotFound) =>
+        {
+            Vec::new()
+        }
+        Err(why) => return Err(why),
+    };
+    files.push(path.to_path_buf());
+    Ok(files)
+}
+
+/// Status of the task recorded by logger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Recorded {
+    Ok,
+    Failed,
+}
+
+/// Tasks recorded in the journal of logger, the last record of the task wins.
+/// Only lines appended since the last refresh are read,
+/// all files are read again once the live file is rotated.
+pub struct JournalIndex {
+    path: PathBuf,
+    records: HashMap<Uuid, Recorded>,
+    /// Device, inode and read offset of the live file.
+    live: Option<(u64, u64, u64)>,
+    built: bool,
+}
+
+impl JournalIndex {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            records: HashMap::new(),
+            live: None,
+            built: false,
+        }
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Recorded> {
+        self.records.get(&id).copied()
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = (Uuid, Recorded)> + '_ {
+        self.records.iter().map(|(id, recorded)| (*id, *recorded))
+    }
+
+    /// Reads what is appended to the journal since the last refresh.
+    pub fn refresh(&mut self) -> Result<()> {
+        let meta = match fs::metadata(&self.path) {
+            Ok(meta) => Some(meta),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context("Getting journal metadata"),
+        };
+
+        match (self.live, meta) {
+            (Some((dev, ino, offset)), Some(meta))
+                if (meta.dev(), meta.ino()) == (dev, ino) && meta.len() >= offset =>
+            {
+                let offset = self.read_live(offset)?;
+                self.live = Some((dev, ino, offset));
+                Ok(())
+            }
+            // Rotated files are only added along with the live file.
+            (None, None) if self.built => Ok(()),
+            (_, meta) => self.rebuild(meta.map(|meta| (meta.dev(), meta.ino()))),
+        }
+    }
+
+    fn rebuild(&mut self, identity: Option<(u64, u64)>) -> Result<()> {
+        self.records.clear();
+
+        let files = journal_files(&self.path)?;
+        for file in &files[..files.len() - 1] {
+            let mut content = Vec::new();
+            let res = File::open(file).and_then(|mut reader| {
+                if file.extension().is_some_and(|ext| ext == "gz") {
+                    GzDecoder::new(reader).read_to_end(&mut content)
+                } else {
+                    reader.read_to_end(&mut content)
+                }
+            });
+            match res {
+                Ok(_) => self.index(&content),
+                // Rotated file is just removed.
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("Reading journal {:?}", file)),
+            }
+        }
+
+        self.live = match identity {
+            Some((dev, ino)) => Some((dev, ino, self.read_live(0)?)),
+            None => None,
+        };
+        self.built = true;
+
+        trace!(records = self.records.len(), "journal indexed");
+
+        Ok(())
+    }
+
+    /// Indexes complete lines of the live file from the offset and returns the offset after them.
+    fn read_live(&mut self, offset: u64) -> Result<u64> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(offset),
+            Err(e) => return Err(e).context("Opening journal"),
+        };
+        file.seek(SeekFrom::Start(offset))
+            .context("Seeking journal")?;
+
+        let mut content = Vec::new();
+        BufReader::new(file)
+            .read_to_end(&mut content)
+            .context("Reading journal")?;
+
+        // Line which is being written is read on the next refresh.
+        let complete = content
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |end| end + 1);
+        self.index(&content[..complete]);
+
+        Ok(offset + complete as u64)
+    }
+
+    fn index(&mut self, content: &[u8]) {
+        for line in content.lines().map_while(Result::ok) {
+            if let Some((id, recorded)) = parse_record(&line) {
+                self.records.insert(id, recorded);
+            }
+        }
+    }
+}
+
+/// Logger records every completed task as a JSON line with its id and status,
+/// older journals have a line with its level and debug representation.
+fn parse_record(line: &str) -> Option<(Uuid, Recorded)> {
+    const TASK_ID: &str = "task: Task { id: ";
+
+    #[derive(Deserialize)]
+    struct Entry {
+        task_id: Uuid,
+        status: Recorded,
+    }
+
+    if let Ok(entry) = serde_json::from_str::<Entry>(line) {
+        return Some((entry.task_id, entry.status));
+    }
+
+    let (_, rest) = line.split_once(TASK_ID)?;
+    let (id, _) = rest.split_once(',')?;
+    let recorded = if line.contains(" ERROR ") {
+        Recorded::Failed
+    } else {
+        Recorded::Ok
+    };
+    Some((id.parse().ok()?, recorded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "processed-tasks.log";
+
+    fn names(files: &[PathBuf]) -> Vec<String> {
+        files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn rotation_order_sorts_counters_as_numbers() {
+        let prefix = format!("{FILE}.");
+        let order = |name: &str| rotation_order(Path::new(name), &prefix);
+
+        assert_eq!(
+            order("processed-tasks.log.2024-10-17.1.gz"),
+            ("2024-10-17".to_string(), 1)
+        );
+        assert!(
+            order("processed-tasks.log.2024-10-17") < order("processed-tasks.log.2024-10-17.2")
+        );
+        assert!(
+            order("processed-tasks.log.2024-10-17.2")
+                < order("processed-tasks.log.2024-10-17.10.gz")
+        );
+        assert!(
+            order("processed-tasks.log.2024-10-17.10") < order("processed-tasks.log.2024-10-18")
+        );
+    }
+
+    #[test]
+    fn journal_files_go_from_the_oldest_to_the_live_one() {
+        let dir = tempfile::tempdir().unwrap();
+        for suffix in [
+            "2024-10-17.10.gz",
+            "2024-10-17.2",
+            "2024-10-18",
+            "2024-10-17",
+        ] {
+            fs::write(dir.path().join(format!("{FILE}.{suffix}")), "").unwrap();
+        }
+        fs::write(dir.path().join("other.log.2024-10-16"), "").unwrap();
+
+        assert_eq!(
+            names(&journal_files(&dir.path().join(FILE)).unwrap()),
+            [
+                "processed-tasks.log.2024-10-17",
+                "processed-tasks.log.2024-10-17.2",
+                "processed-tasks.log.2024-10-17.10.gz",
+                "processed-tasks.log.2024-10-18",
+                "processed-tasks.log",
+            ]
+        );
+        assert_eq!(
+            journal_files(&dir.path().join("missing/journal.log")).unwrap(),
+            [dir.path().join("missing/journal.log")]
+        );
+    }
+
+    fn line(id: u128, status: &str) -> String {
+        format!(
+            "{}\n",
+            serde_json::json!({ "task_id": Uuid::from_u128(id), "status": status })
+        )
+    }
+
+    fn append(path: &Path, content: &str) {
+        use std::io::Write;
+        File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn index_reads_appended_lines_only_once_complete() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        let mut index = JournalIndex::new(&path);
+
+        index.refresh().unwrap();
+        assert_eq!(index.records().count(), 0);
+
+        append(&path, &line(1, "ok"));
+        let partial = line(2, "failed");
+        append(&path, &partial[..10]);
+        index.refresh().unwrap();
+        assert_eq!(index.get(Uuid::from_u128(1)), Some(Recorded::Ok));
+        assert_eq!(index.get(Uuid::from_u128(2)), None);
+
+        append(&path, &partial[10..]);
+        index.refresh().unwrap();
+        assert_eq!(index.get(Uuid::from_u128(2)), Some(Recorded::Failed));
+
+        // Later record of the task wins.
+        append(&path, &line(1, "failed"));
+        index.refresh().unwrap();
+        assert_eq!(index.get(Uuid::from_u128(1)), Some(Recorded::Failed));
+    }
+
+    #[test]
+    fn index_follows_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        let mut index = JournalIndex::new(&path);
+
+        append(&path, &line(1, "ok"));
+        index.refresh().unwrap();
+
+        // Lines appended right before rotation are read from the rotated file.
+        append(&path, &line(2, "ok"));
+        fs::rename(&path, dir.path().join(format!("{FILE}.2024-10-17"))).unwrap();
+        append(&path, &line(3, "failed"));
+        index.refresh().unwrap();
+
+        let mut ids = index.records().map(|(id, _)| id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, [1, 2, 3].map(Uuid::from_u128));
+    }
+
+    #[test]
+    fn index_reads_legacy_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        let id = Uuid::from_u128(1);
+        append(
+            &path,
+            &format!(
+                "2024-10-17T10:00:00Z ERROR task_logger: comp_task: CompletedTask {{ task: Task {{ id: {id}, title: \"t\" }} }}\n"
+            ),
+        );
+
+        let mut index = JournalIndex::new(&path);
+        index.refresh().unwrap();
+        assert_eq!(index.get(id), Some(Recorded::Failed));
+    }
+}
@@ -1,3 +1,14 @@
+mod codec;
+mod journal;
+mod schema;
 mod task;
+mod transport;
 
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
+pub use codec::*;
+pub use journal::*;
+pub use schema::*;
 pub use task::*;
+pub use transport::*;
@@ -0,0 +1,350 @@
+use crate::{CompletedTask, Task};
+use anyhow::{bail, Context, Result};
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Version of the format tasks are written in, it grows once fields change.
+/// Files without version are written before versions were introduced, i.e. of version 1.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Field which keeps version next to fields of the task.
+const VERSION_FIELD: &str = "schema_version";
+
+/// Task written with version of its format.
+#[derive(Serialize, JsonSchema)]
+struct Envelope<T> {
+    /// Version of the format, files without it are of version 1.
+    schema_version: u32,
+    #[serde(flatten)]
+    body: T,
+}
+
+/// Type which is written into files and queues with version of its format.
+pub trait Schema: Serialize + DeserializeOwned + JsonSchema {
+    /// Upgrades fields of the older version to the next version.
+    fn upgrade(fields: &mut Map<String, Value>, version: u32) -> Result<()>;
+}
+
+impl Schema for Task {
+    fn upgrade(fields: &mut Map<String, Value>, version: u32) -> Result<()> {
+        match version {
+            // Executors, retries, scheduling and dependencies.
+            1 => introduce(
+                fields,
+                [
+                    ("kind", json!(Task::DEFAULT_KIND)),
+                    ("args", json!([])),
+                    ("retry", Value::Null),
+                    ("priority", json!(0)),
+                    ("run_at", Value::Null),
+                    ("depends_on", json!([])),
+                ],
+            ),
+            // Payloads and attachments.
+            2 => introduce(
+                fields,
+                [("payload", Value::Null), ("attachments", json!([]))],
+            ),
+            _ => bail!("No upgrade of task from version {}", version),
+        }
+        Ok(())
+    }
+}
+
+impl Schema for CompletedTask {
+    fn upgrade(fields: &mut Map<String, Value>, version: u32) -> Result<()> {
+        // Completed task embeds the task of the same version.
+        if let Some(Value::Object(task)) = fields.get_mut("task") {
+            Task::upgrade(task, version).context("Upgrading embedded task")?;
+        }
+
+        match version {
+            1 => {}
+            // Output was either value or error, details of execution are kept beside it since.
+            2 => {
+                let output = fields.get_mut("output").context("Missing output")?;
+                let Value::Object(output) = output else {
+                    bail!("Expected output object, got {}", output);
+                };
+                let is_result = |field| output.len() == 1 && output.contains_key(field);
+                if !is_result("value") && !is_result("error") {
+                    bail!("Expected output of either value or error");
+                }
+                introduce(
+                    output,
+                    [
+                        ("data", Value::Null),
+                        ("exit_code", Value::Null),
+                        ("stdout", Value::Null),
+                        ("stderr", Value::Null),
+                        ("artifacts", json!([])),
+                    ],
+                );
+            }
+            _ => bail!("No upgrade of completed task from version {}", version),
+        }
+        Ok(())
+    }
+}
+
+/// Adds fields which the next version introduces with their defaults.
+fn introduce<const N: usize>(fields: &mut Map<String, Value>, introduced: [(&str, Value); N]) {
+    for (name, value) in introduced {
+        fields.entry(name).or_insert(value);
+    }
+}
+
+/// Types which are written into files and queues, for tools which print their schemas.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum SchemaOf {
+    /// Created task, i.e. input of processor
+    Task,
+    /// Handled task, i.e. input of logger
+    Completed,
+}
+
+impl SchemaOf {
+    pub fn json_schema(self) -> RootSchema {
+        match self {
+            SchemaOf::Task => json_schema::<Task>(),
+            SchemaOf::Completed => json_schema::<CompletedTask>(),
+        }
+    }
+}
+
+pub fn to_json<T: Schema>(value: &T) -> Result<Vec<u8>> {
+    serde_json::to_vec(&to_value(value)?).context("Serializing JSON")
+}
+
+/// Reads the value of any known version, older ones are upgraded step by step.
+pub fn from_json<T: Schema>(json: &[u8]) -> Result<T> {
+    from_value(serde_json::from_slice(json).context("Parsing JSON")?)
+}
+
+pub(crate) fn to_value<T: Schema>(value: &T) -> Result<Value> {
+    serde_json::to_value(Envelope {
+        schema_version: SCHEMA_VERSION,
+        body: value,
+    })
+    .context("Serializing with schema version")
+}
+
+pub(crate) fn from_value<T: Schema>(value: Value) -> Result<T> {
+    let Value::Object(mut fields) = value else {
+        bail!("Expected object, got {}", value);
+    };
+
+    let version = match fields.remove(VERSION_FIELD) {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .with_context(|| format!("Invalid schema version: {}", version))?,
+    };
+
+    if version == 0 || version > SCHEMA_VERSION {
+        bail!(
+            "Unsupported schema version {}, the newest known is {}",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+
+    for version in version..SCHEMA_VERSION {
+        T::upgrade(&mut fields, version)
+            .with_context(|| format!("Upgrading from schema version {}", version))?;
+    }
+
+    serde_json::from_value(Value::Object(fields)).context("Getting value of the current schema")
+}
+
+/// JSON Schema of the current version, which producers may validate their files with.
+pub fn json_schema<T: Schema>() -> RootSchema {
+    schema_for!(Envelope<T>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, TaskResult};
+    use std::path::Path;
+    use uuid::Uuid;
+
+    /// Task of version 1, before kinds, payloads and schedules of tasks.
+    fn task_v1() -> Value {
+        json!({
+            "id": Uuid::from_u128(1),
+            "title": "title",
+            "description": "description",
+            "created_at": "2024-10-17T10:00:00Z",
+            "complete_until": null,
+        })
+    }
+
+    #[test]
+    fn from_value_reads_version_1_without_version_field() {
+        let task = from_value::<Task>(task_v1()).unwrap();
+
+        assert_eq!(task.id, Uuid::from_u128(1));
+        assert_eq!(task.kind, Task::DEFAULT_KIND);
+        assert!(task.args.is_empty());
+        assert!(task.payload.is_null());
+        assert_eq!(task.priority, 0);
+        assert!(task.run_at.is_none());
+        assert!(task.depends_on.is_empty());
+    }
+
+    #[test]
+    fn from_value_reads_version_2() {
+        let mut value = task_v1();
+        value[VERSION_FIELD] = json!(2);
+        value["kind"] = json!("shell");
+        value["args"] = json!(["echo hello"]);
+        value["priority"] = json!(5);
+
+        let task = from_value::<Task>(value).unwrap();
+
+        assert_eq!(task.kind, "shell");
+        assert_eq!(task.args, ["echo hello"]);
+        assert_eq!(task.priority, 5);
+        assert!(task.payload.is_null());
+    }
+
+    #[test]
+    fn from_value_reads_completed_task_of_version_1() {
+        let value = json!({
+            "id": Uuid::from_u128(2),
+            "task": task_v1(),
+            "output": { "value": "done" },
+            "completed_at": "2024-10-17T10:01:00Z",
+        });
+
+        let comp_task = from_value::<CompletedTask>(value).unwrap();
+
+        assert_eq!(comp_task.task.kind, Task::DEFAULT_KIND);
+        assert!(matches!(
+            comp_task.output.result,
+            TaskResult::Value(Some(ref value)) if value == "done"
+        ));
+    }
+
+    #[test]
+    fn decode_reads_files_of_version_1() {
+        let task = crate::decode::<Task>(
+            Path::new("task.json"),
+            include_bytes!("../fixtures/task_v1.json"),
+        )
+        .unwrap();
+        assert_eq!(task.id, Uuid::from_u128(1));
+        assert_eq!(task.kind, Task::DEFAULT_KIND);
+
+        let comp_task = crate::decode::<CompletedTask>(
+            Path::new("completed.json"),
+            include_bytes!("../fixtures/completed_task_v1.json"),
+        )
+        .unwrap();
+        assert_eq!(comp_task.id, Uuid::from_u128(2));
+        assert_eq!(comp_task.task.id, Uuid::from_u128(1));
+        assert!(comp_task.task.complete_until.is_some());
+        assert!(matches!(
+            comp_task.output.result,
+            TaskResult::Error(ref error) if error == "deadline exceeded"
+        ));
+        assert!(comp_task.output.exit_code.is_none());
+        assert!(comp_task.output.artifacts.is_empty());
+    }
+
+    #[test]
+    fn decode_reads_files_of_version_2() {
+        let comp_task = crate::decode::<CompletedTask>(
+            Path::new("completed.json"),
+            include_bytes!("../fixtures/completed_task_v2.json"),
+        )
+        .unwrap();
+
+        assert_eq!(comp_task.task.kind, "shell");
+        assert_eq!(comp_task.task.priority, 5);
+        assert_eq!(comp_task.task.retry.unwrap().max_attempts, 3);
+        assert_eq!(comp_task.task.depends_on, [Uuid::from_u128(3)]);
+        assert!(comp_task.task.payload.is_null());
+        assert!(comp_task.output.is_ok());
+    }
+
+    #[test]
+    fn upgrade_adds_fields_of_each_version() {
+        let Value::Object(mut fields) = task_v1() else {
+            unreachable!()
+        };
+
+        Task::upgrade(&mut fields, 1).unwrap();
+        assert_eq!(fields["kind"], json!(Task::DEFAULT_KIND));
+        assert_eq!(fields["depends_on"], json!([]));
+        assert!(!fields.contains_key("payload"));
+
+        Task::upgrade(&mut fields, 2).unwrap();
+        assert_eq!(fields["attachments"], json!([]));
+        assert!(Task::upgrade(&mut fields, SCHEMA_VERSION).is_err());
+    }
+
+    #[test]
+    fn upgrade_keeps_fields_set_by_older_version() {
+        let mut value = task_v1();
+        value["kind"] = json!("shell");
+        let Value::Object(mut fields) = value else {
+            unreachable!()
+        };
+
+        Task::upgrade(&mut fields, 1).unwrap();
+
+        assert_eq!(fields["kind"], json!("shell"));
+    }
+
+    #[test]
+    fn upgrade_rejects_unknown_output_of_version_2() {
+        for output in [
+            json!({}),
+            json!({ "value": "a", "error": "b" }),
+            json!("done"),
+        ] {
+            let value = json!({
+                VERSION_FIELD: 2,
+                "id": Uuid::from_u128(2),
+                "task": task_v1(),
+                "output": output.clone(),
+                "completed_at": "2024-10-17T10:01:00Z",
+            });
+            assert!(from_value::<CompletedTask>(value).is_err(), "{}", output);
+        }
+    }
+
+    #[test]
+    fn schema_of_is_schema_of_the_type() {
+        assert_eq!(SchemaOf::Task.json_schema(), json_schema::<Task>());
+        assert_eq!(
+            SchemaOf::Completed.json_schema(),
+            json_schema::<CompletedTask>()
+        );
+    }
+
+    #[test]
+    fn to_value_writes_current_version() {
+        let task = test_util::task(1);
+        let value = to_value(&task).unwrap();
+
+        assert_eq!(value[VERSION_FIELD], json!(SCHEMA_VERSION));
+        assert_eq!(from_value::<Task>(value).unwrap().id, task.id);
+    }
+
+    #[test]
+    fn from_value_rejects_unknown_versions() {
+        for version in [json!(0), json!(SCHEMA_VERSION + 1), json!("2"), json!(-1)] {
+            let mut value = task_v1();
+            value[VERSION_FIELD] = version.clone();
+            assert!(from_value::<Task>(value).is_err(), "{}", version);
+        }
+
+        assert!(from_value::<Task>(json!([task_v1()])).is_err());
+    }
+}
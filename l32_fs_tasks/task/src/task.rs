@@ -1,24 +1,138 @@
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use uuid::Uuid;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Task {
     pub id: Uuid,
+    /// Name of executor which handles the task.
+    #[serde(default = "Task::default_kind")]
+    pub kind: String,
+    /// Executor specific arguments.
+    #[serde(default)]
+    pub args: Vec<String>,
     pub title: String,
     pub description: String,
+    /// Executor specific parameters of any structure.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub payload: Value,
+    /// Files kept in the attachments folder of the task.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
     pub created_at: DateTime<Utc>,
     pub complete_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+    /// Tasks with higher priority run first.
+    #[serde(default)]
+    pub priority: i32,
+    /// Task is held until the time.
+    #[serde(default)]
+    pub run_at: Option<DateTime<Utc>>,
+    /// Tasks which must complete successfully before this one runs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<Uuid>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Task {
+    pub const DEFAULT_KIND: &'static str = "noop";
+
+    pub fn default_kind() -> String {
+        Self::DEFAULT_KIND.to_string()
+    }
+
+    /// Folder of attachments beside task files, it is hidden from watchers of the folder.
+    pub fn attachments_dir(&self, tasks: &Path) -> PathBuf {
+        tasks.join(format!(".{}.attachments", self.id))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Attachment {
+    /// Name of the file in the attachments folder.
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema)]
+pub struct RetryPolicy {
+    /// Number of attempts including the first one.
+    pub max_attempts: u32,
+    /// Delay after the first failed attempt, doubled for each next one.
+    #[serde(default)]
+    pub backoff_ms: u64,
+    #[serde(default)]
+    pub max_backoff_ms: Option<u64>,
+}
+
+impl RetryPolicy {
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        let ms = self.backoff_ms.saturating_mul(factor);
+        Duration::from_millis(self.max_backoff_ms.map_or(ms, |max| ms.min(max)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
-pub enum TaskOutput {
+pub enum TaskResult {
     Value(Option<String>),
     Error(String),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Result of the task with details of its execution,
+/// details are absent in outputs of executors which don't have them.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+pub struct TaskOutput {
+    #[serde(flatten)]
+    pub result: TaskResult,
+    /// Structured result of the executor.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub data: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+    /// Files produced by the executor.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<PathBuf>,
+}
+
+impl TaskOutput {
+    pub fn value(value: Option<String>) -> Self {
+        Self::new(TaskResult::Value(value))
+    }
+
+    pub fn error(error: String) -> Self {
+        Self::new(TaskResult::Error(error))
+    }
+
+    fn new(result: TaskResult) -> Self {
+        Self {
+            result,
+            data: Value::Null,
+            exit_code: None,
+            stdout: None,
+            stderr: None,
+            artifacts: Vec::new(),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        matches!(self.result, TaskResult::Value(_))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 pub struct CompletedTask {
     pub id: Uuid,
     pub task: Task,
@@ -26,3 +140,64 @@ pub struct CompletedTask {
     pub completed_at: DateTime<Utc>,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(backoff_ms: u64, max_backoff_ms: Option<u64>) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            backoff_ms,
+            max_backoff_ms,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_after_each_attempt() {
+        let policy = policy(100, None);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(5), Duration::from_millis(1600));
+    }
+
+    #[test]
+    fn backoff_before_first_attempt_is_initial_delay() {
+        assert_eq!(policy(100, None).backoff(0), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_is_capped_by_max() {
+        let policy = policy(100, Some(300));
+
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff(10), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn backoff_saturates_instead_of_overflowing() {
+        assert_eq!(
+            policy(100, None).backoff(u32::MAX),
+            Duration::from_millis(u64::MAX)
+        );
+        assert_eq!(
+            policy(100, Some(60_000)).backoff(100),
+            Duration::from_millis(60_000)
+        );
+    }
+
+    #[test]
+    fn zero_backoff_retries_at_once() {
+        assert_eq!(policy(0, None).backoff(7), Duration::ZERO);
+    }
+
+    #[test]
+    fn backoff_fields_default_when_absent() {
+        let policy: RetryPolicy = serde_json::from_str(r#"{"max_attempts": 3}"#).unwrap();
+
+        assert_eq!(policy.max_attempts, 3);
+        assert_eq!(policy.backoff(3), Duration::ZERO);
+    }
+}
@@ -0,0 +1,23 @@
+use crate::Task;
+use chrono::Utc;
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Task of the current version with the id, other fields are set through struct update.
+pub fn task(id: u128) -> Task {
+    Task {
+        id: Uuid::from_u128(id),
+        kind: Task::default_kind(),
+        args: Vec::new(),
+        title: "title".to_string(),
+        description: "description".to_string(),
+        payload: Value::Null,
+        attachments: Vec::new(),
+        created_at: Utc::now(),
+        complete_until: None,
+        retry: None,
+        priority: 0,
+        run_at: None,
+        depends_on: Vec::new(),
+    }
+}
@@ -0,0 +1,104 @@
+mod dir;
+mod inbox;
+mod sqlite;
+
+use crate::{Codec, CompletedTask, Schema, Task};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+pub use inbox::{Inbox, Received};
+pub use sqlite::{Claimed, Queue, State, Store};
+
+/// Queue of created tasks, i.e. input of processor.
+pub const TASKS: &str = "tasks";
+/// Queue of handled tasks, i.e. input of logger.
+pub const COMPLETED: &str = "completed";
+
+/// What is handed off from one stage of the pipeline to the next one.
+pub trait Message: Schema + Debug + Send + Sync + 'static {
+    fn id(&self) -> Uuid;
+
+    /// Messages with higher priority are claimed first.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    /// Message is not claimed before the time.
+    fn run_at(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+
+    /// Folder which belongs to the message in the folder of its stage.
+    fn attachments(&self, _dir: &Path) -> Option<PathBuf> {
+        None
+    }
+}
+
+impl Message for Task {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    fn run_at(&self) -> Option<DateTime<Utc>> {
+        self.run_at
+    }
+
+    fn attachments(&self, dir: &Path) -> Option<PathBuf> {
+        Some(self.attachments_dir(dir))
+    }
+}
+
+/// Completed task is keyed by its task, so its file is found by id of the task.
+impl Message for CompletedTask {
+    fn id(&self) -> Uuid {
+        self.task.id
+    }
+}
+
+/// Message which can't be handled, kept with its error.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Message with the same id is sent already, e.g. before a restart.
+#[derive(Debug)]
+pub struct AlreadySent(pub Uuid);
+
+impl Display for AlreadySent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Message {} is already sent", self.0)
+    }
+}
+
+impl Error for AlreadySent {}
+
+/// Where a stage of the pipeline sends its messages to.
+pub enum TaskTransport {
+    /// Files in the folder written with the codec, the next stage watches it with `notifier`.
+    Dir(PathBuf, Codec),
+    /// Queue of the embedded store, the next stage claims messages from it.
+    Sqlite(Queue),
+}
+
+impl TaskTransport {
+    /// Fails with [`AlreadySent`] if the message with the same id is there already.
+    pub async fn send<M: Message>(&self, message: &M) -> Result<()> {
+        match self {
+            TaskTransport::Dir(dir, codec) => dir::save(message, dir, *codec).await,
+            TaskTransport::Sqlite(queue) => queue.send(message).await,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{AlreadySent, Codec, Message};
+use anyhow::{Context, Result};
+use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
+use tracing::{debug, trace};
+
+pub(super) async fn save(message: &impl Message, dir: &Path, codec: Codec) -> Result<()> {
+    debug!(?message, "saving task file");
+
+    let filename = codec.file_name(message.id());
+    let path = dir.join(&filename);
+    // Task file is written under temporary name and renamed once complete,
+    // so watchers never see partially written tasks.
+    let tmp_path = path.with_file_name(format!(".{}.tmp", filename));
+
+    let mut file = File::create_new(&tmp_path)
+        .await
+        .inspect(|_| {
+            trace!(
+                file = tmp_path.to_string_lossy().as_ref(),
+                "temporary task file created"
+            )
+        })
+        .context("Creating temporary task file")?;
+
+    let res = async {
+        let content = codec
+            .encode(message)
+            .inspect(|bytes| trace!(bytes = bytes.len(), "task serialized"))
+            .context("Serializing task")?;
+
+        file.write_all(&content)
+            .await
+            .inspect(|_| trace!("written {} bytes", content.len()))
+            .context("Saving task into file")?;
+
+        file.sync_all().await.context("Flushing task file")?;
+        // Closing after rename would report the file once again.
+        drop(file);
+
+        // Existing file is never overwritten, it's checked by the move itself.
+        let (from, to) = (tmp_path.clone(), path.clone());
+        match tokio::task::spawn_blocking(move || rename_noreplace(&from, &to))
+            .await
+            .context("Waiting for task file to move")?
+        {
+            Ok(()) => {
+                trace!(file = path.to_string_lossy().as_ref(), "task file moved");
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Err(AlreadySent(message.id())).context("Moving task file into place")
+            }
+            Err(e) => Err(e).context("Moving task file into place"),
+        }
+    }
+    .await;
+
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path).await;
+    }
+
+    res
+}
+
+/// Renames the file unless the target exists, watchers notice it like any rename.
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    let (c_from, c_to) = (
+        CString::new(from.as_os_str().as_bytes())?,
+        CString::new(to.as_os_str().as_bytes())?,
+    );
+    // SAFETY: both paths are NUL-terminated strings which outlive the call.
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            c_from.as_ptr(),
+            libc::AT_FDCWD,
+            c_to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if res == 0 {
+        return Ok(());
+    }
+
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        // Filesystem can't do it, e.g. a network one which is watched by polling anyway.
+        Some(libc::EINVAL | libc::ENOSYS) => {
+            std::fs::hard_link(from, to)?;
+            std::fs::remove_file(from)
+        }
+        _ => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, Task};
+    use uuid::Uuid;
+
+    fn task(title: &str) -> Task {
+        Task {
+            title: title.to_string(),
+            ..test_util::task(1)
+        }
+    }
+
+    #[tokio::test]
+    async fn save_never_overwrites_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        save(&task("first"), dir.path(), Codec::default())
+            .await
+            .unwrap();
+
+        let why = save(&task("second"), dir.path(), Codec::default())
+            .await
+            .unwrap_err();
+
+        assert!(why.is::<AlreadySent>());
+        let path = dir
+            .path()
+            .join(Codec::default().file_name(Uuid::from_u128(1)));
+        let saved = crate::decode::<Task>(&path, &std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved.title, "first");
+        // Temporary file is removed either way.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn rename_noreplace_moves_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("from"), dir.path().join("to"));
+        std::fs::write(&from, "content").unwrap();
+
+        rename_noreplace(&from, &to).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "content");
+    }
+}
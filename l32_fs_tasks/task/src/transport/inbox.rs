@@ -0,0 +1,487 @@
+use super::{Claimed, DeadLetter, Message, Queue, TaskTransport};
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use notifier::{move_file, Bookkeeper};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::fs;
+use tracing::{debug, error, trace, warn};
+use uuid::Uuid;
+
+/// Where a stage of the pipeline takes its messages from.
+/// Files of the folder and messages of the store share one retry and dead-letter path.
+pub struct Inbox {
+    /// Queue to claim messages from, otherwise files of the folder are opened.
+    queue: Option<Queue>,
+    /// Consumes files of the folder and attachments of messages.
+    bookkeeper: Bookkeeper,
+    /// Folder of files which can't be handled, the store keeps its own dead letters.
+    dead_letter: PathBuf,
+}
+
+/// Message taken from the inbox until it is settled.
+#[derive(Debug)]
+pub enum Received<M> {
+    /// File of the folder, its attempts are counted beside it.
+    File {
+        path: PathBuf,
+        attempt: u32,
+        message: M,
+    },
+    /// Message claimed from the queue.
+    Claimed(Claimed<M>),
+}
+
+impl<M> Received<M> {
+    /// Number of attempts including this one.
+    pub fn attempt(&self) -> u32 {
+        match self {
+            Received::File { attempt, .. } => *attempt,
+            Received::Claimed(claimed) => claimed.attempt,
+        }
+    }
+
+    pub fn message(&self) -> &M {
+        match self {
+            Received::File { message, .. } => message,
+            Received::Claimed(claimed) => &claimed.message,
+        }
+    }
+}
+
+impl Inbox {
+    pub fn new(queue: Option<Queue>, bookkeeper: Bookkeeper, dead_letter: PathBuf) -> Self {
+        Self {
+            queue,
+            bookkeeper,
+            dead_letter,
+        }
+    }
+
+    /// Reads the file of the folder and counts the attempt,
+    /// unreadable file is moved into dead-letter folder at once.
+    /// Returns nothing if the file is gone or unreadable.
+    pub async fn open<M: Message>(&self, path: &Path) -> Result<Option<Received<M>>> {
+        let attempt = next_attempt(path).await?;
+
+        let content = match fs::read(path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                debug!(file = ?path, "file is gone");
+                reset(path).await?;
+                return Ok(None);
+            }
+            Err(e) => return Err(e).context("Reading file"),
+        };
+
+        match crate::decode(path, &content) {
+            Ok(message) => Ok(Some(Received::File {
+                path: path.to_path_buf(),
+                attempt,
+                message,
+            })),
+            // Unreadable file would be reported on every start otherwise.
+            Err(why) => {
+                let why = why.context("Getting message from file");
+                error!(file = ?path, "failed to read file: {:?}", why);
+                self.bury(path, None, &why, attempt).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Takes the next due message of the queue.
+    pub async fn claim<M: Message>(
+        &self,
+        worker: &str,
+        lease: Duration,
+    ) -> Result<Option<Received<M>>> {
+        let claimed = self.queue()?.claim(worker, lease).await?;
+        Ok(claimed.map(Received::Claimed))
+    }
+
+    /// Extends the lease of the claimed message, files are leased by their stage.
+    pub async fn renew<M>(&self, received: &Received<M>, lease: Duration) -> Result<()> {
+        match received {
+            Received::File { .. } => Ok(()),
+            Received::Claimed(claimed) => self.queue()?.renew(claimed, lease).await,
+        }
+    }
+
+    /// Sends the next message and consumes the received one,
+    /// both are done in one transaction if they share the store.
+    pub async fn forward<M: Message, N: Message>(
+        &self,
+        received: &Received<M>,
+        next: &TaskTransport,
+        message: &N,
+    ) -> Result<()> {
+        match (received, next) {
+            (Received::Claimed(claimed), TaskTransport::Sqlite(next)) => {
+                self.queue()?.forward(claimed, next, message).await?;
+                self.consume_attachments(received).await
+            }
+            _ => {
+                next.send(message).await?;
+                self.ack(received).await
+            }
+        }
+    }
+
+    /// Consumes the received message and its attachments.
+    pub async fn ack<M: Message>(&self, received: &Received<M>) -> Result<()> {
+        match received {
+            Received::File { path, .. } => {
+                self.bookkeeper
+                    .consume(path)
+                    .await
+                    .context("Consuming file")?;
+                reset(path).await?;
+            }
+            Received::Claimed(claimed) => self.queue()?.ack(claimed).await?,
+        }
+        self.consume_attachments(received).await
+    }
+
+    /// Returns the failed message, it is taken again once the delay passes.
+    pub async fn retry<M>(&self, received: &Received<M>, delay: Duration) -> Result<()> {
+        match received {
+            Received::File { path, attempt, .. } => schedule(path, *attempt, delay).await,
+            Received::Claimed(claimed) => self.queue()?.release(claimed, delay).await,
+        }
+    }
+
+    /// Returns the message like [`Inbox::retry`], but the attempt is not counted.
+    pub async fn postpone<M>(&self, received: &Received<M>, delay: Duration) -> Result<()> {
+        match received {
+            Received::File { path, attempt, .. } => {
+                schedule(path, attempt.saturating_sub(1), delay).await
+            }
+            Received::Claimed(claimed) => self.queue()?.postpone(claimed, delay).await,
+        }
+    }
+
+    /// Keeps the message which can't be handled with its error,
+    /// attachments follow it, so it can be requeued as a whole.
+    pub async fn dead_letter<M: Message>(
+        &self,
+        received: &Received<M>,
+        error: &anyhow::Error,
+    ) -> Result<()> {
+        let attachments = received.message().attachments(self.bookkeeper.dir());
+        match received {
+            Received::File { path, attempt, .. } => {
+                self.bury(path, attachments.as_deref(), error, *attempt)
+                    .await
+            }
+            Received::Claimed(claimed) => {
+                self.queue()?.dead_letter(claimed, error).await?;
+                match attachments {
+                    Some(attachments) => self.move_attachments(&attachments).await,
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Time of the next attempt of the failed file.
+    pub async fn retry_at(path: &Path) -> Option<DateTime<Utc>> {
+        load(path)
+            .await
+            .inspect_err(|why| debug!(file = ?path, "failed to read attempts: {:?}", why))
+            .ok()?
+            .retry_at
+    }
+
+    /// Dead letter of the file which is moved into dead-letter folder.
+    pub async fn read_dead_letter(path: &Path, id: Uuid) -> Result<DeadLetter> {
+        #[derive(Deserialize)]
+        struct Error {
+            error: String,
+            failed_at: DateTime<Utc>,
+        }
+
+        let mut error_file = path.as_os_str().to_owned();
+        error_file.push(".error");
+        let content = fs::read(error_file).await.context("Reading error file")?;
+        let Error { error, failed_at } =
+            serde_json::from_slice(&content).context("Getting error from file")?;
+
+        Ok(DeadLetter {
+            id,
+            error,
+            failed_at,
+        })
+    }
+
+    fn queue(&self) -> Result<&Queue> {
+        self.queue.as_ref().context("Inbox has no queue")
+    }
+
+    async fn consume_attachments<M: Message>(&self, received: &Received<M>) -> Result<()> {
+        match received.message().attachments(self.bookkeeper.dir()) {
+            Some(attachments) => self
+                .bookkeeper
+                .consume_dir(&attachments)
+                .await
+                .context("Consuming attachments"),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the file into dead-letter folder and puts the error beside it.
+    async fn bury(
+        &self,
+        path: &Path,
+        attachments: Option<&Path>,
+        error: &anyhow::Error,
+        attempts: u32,
+    ) -> Result<()> {
+        let filename = path.file_name().context("Getting file name")?;
+
+        fs::create_dir_all(&self.dead_letter)
+            .await
+            .context("Creating dead-letter folder")?;
+
+        let dead_file = self.dead_letter.join(filename);
+        move_file(path, &dead_file)
+            .await
+            .context("Moving file into dead-letter folder")?;
+        warn!(file = ?dead_file, "file moved into dead-letter folder");
+
+        if let Some(attachments) = attachments {
+            self.move_attachments(attachments).await?;
+        }
+
+        let content = serde_json::to_vec(&serde_json::json!({
+            "error": format!("{:#}", error),
+            "attempts": attempts,
+            "failed_at": Utc::now(),
+        }))
+        .context("Serializing error")?;
+
+        let mut error_file = dead_file.into_os_string();
+        error_file.push(".error");
+        fs::write(error_file, content)
+            .await
+            .context("Writing error file")?;
+
+        reset(path).await
+    }
+
+    async fn move_attachments(&self, attachments: &Path) -> Result<()> {
+        let name = attachments
+            .file_name()
+            .context("Getting attachments folder name")?;
+        fs::create_dir_all(&self.dead_letter)
+            .await
+            .context("Creating dead-letter folder")?;
+        match fs::rename(attachments, self.dead_letter.join(name)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                Err(e).context("Moving attachments into dead-letter folder")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Attempts made so far and when the next one is due.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Attempts {
+    attempts: u32,
+    retry_at: Option<DateTime<Utc>>,
+}
+
+/// Attempts are kept beside the file, so they survive restarts.
+fn attempts_file(path: &Path) -> PathBuf {
+    let mut attempts = OsString::from(path);
+    attempts.push(".attempts");
+    PathBuf::from(attempts)
+}
+
+async fn load(path: &Path) -> Result<Attempts> {
+    let content = match fs::read_to_string(attempts_file(path)).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Attempts::default()),
+        Err(e) => return Err(e).context("Reading attempts file"),
+    };
+
+    // Files of older versions keep only the counter.
+    if let Ok(attempts) = content.trim().parse::<u32>() {
+        return Ok(Attempts {
+            attempts,
+            retry_at: None,
+        });
+    }
+    Ok(serde_json::from_str(&content).unwrap_or_default())
+}
+
+async fn store(path: &Path, attempts: &Attempts) -> Result<()> {
+    let content = serde_json::to_vec(attempts).context("Serializing attempts")?;
+    fs::write(attempts_file(path), content)
+        .await
+        .inspect(|_| trace!(?attempts, "attempts persisted"))
+        .context("Writing attempts file")
+}
+
+/// Increments persisted counter of attempts and returns the current attempt.
+async fn next_attempt(path: &Path) -> Result<u32> {
+    let attempts = load(path).await?;
+
+    let attempts = Attempts {
+        attempts: attempts.attempts + 1,
+        retry_at: None,
+    };
+    store(path, &attempts).await?;
+
+    Ok(attempts.attempts)
+}
+
+/// Persists the time of the next attempt, so the file is held until then after restarts as well.
+async fn schedule(path: &Path, attempts: u32, delay: Duration) -> Result<()> {
+    let retry_at = Utc::now() + TimeDelta::from_std(delay).context("Getting retry time")?;
+    store(
+        path,
+        &Attempts {
+            attempts,
+            retry_at: Some(retry_at),
+        },
+    )
+    .await
+}
+
+async fn reset(path: &Path) -> Result<()> {
+    match fs::remove_file(attempts_file(path)).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e).context("Removing attempts file"),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::task, Codec, Store, Task, TASKS};
+    use notifier::Consume;
+
+    const LEASE: Duration = Duration::from_secs(60);
+
+    fn inbox(dir: &Path, queue: Option<Queue>) -> Inbox {
+        Inbox::new(
+            queue,
+            Bookkeeper::new(dir, Consume::Archive),
+            dir.join("dead-letter"),
+        )
+    }
+
+    async fn file(dir: &Path, task: &Task) -> PathBuf {
+        TaskTransport::Dir(dir.to_path_buf(), Codec::default())
+            .send(task)
+            .await
+            .unwrap();
+        dir.join(Codec::default().file_name(task.id))
+    }
+
+    #[tokio::test]
+    async fn file_is_retried_at_persisted_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let inbox = inbox(dir.path(), None);
+        let path = file(dir.path(), &task(1)).await;
+
+        let received = inbox.open::<Task>(&path).await.unwrap().unwrap();
+        assert_eq!(received.attempt(), 1);
+        assert_eq!(Inbox::retry_at(&path).await, None);
+
+        inbox.retry(&received, LEASE).await.unwrap();
+        assert!(Inbox::retry_at(&path)
+            .await
+            .is_some_and(|at| at > Utc::now()));
+
+        let received = inbox.open::<Task>(&path).await.unwrap().unwrap();
+        assert_eq!(received.attempt(), 2);
+        inbox.postpone(&received, Duration::ZERO).await.unwrap();
+        let received = inbox.open::<Task>(&path).await.unwrap().unwrap();
+        assert_eq!(received.attempt(), 2);
+
+        inbox.ack(&received).await.unwrap();
+        assert!(!path.exists());
+        assert!(!attempts_file(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn file_is_dead_lettered_with_attachments_and_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let inbox = inbox(dir.path(), None);
+        let task = task(1);
+        let path = file(dir.path(), &task).await;
+        fs::create_dir(task.attachments_dir(dir.path()))
+            .await
+            .unwrap();
+
+        let received = inbox.open::<Task>(&path).await.unwrap().unwrap();
+        inbox
+            .dead_letter(&received, &anyhow::anyhow!("failed"))
+            .await
+            .unwrap();
+
+        let dead_letter = dir.path().join("dead-letter");
+        let dead_file = dead_letter.join(path.file_name().unwrap());
+        assert!(!path.exists());
+        assert!(dead_file.exists());
+        assert!(task.attachments_dir(&dead_letter).exists());
+
+        let letter = Inbox::read_dead_letter(&dead_file, task.id).await.unwrap();
+        assert_eq!(letter.error, "failed");
+    }
+
+    #[tokio::test]
+    async fn unreadable_file_is_dead_lettered_at_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let inbox = inbox(dir.path(), None);
+        let path = dir.path().join("broken.json");
+        fs::write(&path, "{").await.unwrap();
+
+        assert!(inbox.open::<Task>(&path).await.unwrap().is_none());
+        assert!(!path.exists());
+        assert!(dir.path().join("dead-letter/broken.json.error").exists());
+    }
+
+    #[tokio::test]
+    async fn claimed_message_is_dead_lettered_in_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = Store::open(":memory:").await.unwrap().queue(TASKS);
+        let inbox = inbox(dir.path(), Some(queue.clone()));
+        let task = task(1);
+        queue.send(&task).await.unwrap();
+        fs::create_dir(task.attachments_dir(dir.path()))
+            .await
+            .unwrap();
+
+        let received = inbox.claim::<Task>("worker", LEASE).await.unwrap().unwrap();
+        inbox
+            .dead_letter(&received, &anyhow::anyhow!("failed"))
+            .await
+            .unwrap();
+
+        assert!(inbox
+            .claim::<Task>("worker", Duration::ZERO)
+            .await
+            .unwrap()
+            .is_none());
+        let letter = queue.dead_lettered(task.id).await.unwrap().unwrap();
+        assert_eq!(letter.error, "failed");
+        assert_eq!(queue.dead_letters().await.unwrap().len(), 1);
+        assert!(queue
+            .dead_lettered(Uuid::from_u128(2))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(task
+            .attachments_dir(&dir.path().join("dead-letter"))
+            .exists());
+    }
+}
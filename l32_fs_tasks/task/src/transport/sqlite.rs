@@ -0,0 +1,755 @@
+use crate::{from_json, to_json, AlreadySent, DeadLetter, Message};
+use anyhow::{ensure, Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension, TransactionBehavior};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{error, trace, warn};
+use uuid::Uuid;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS messages (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue TEXT NOT NULL,
+    id TEXT NOT NULL,
+    priority INTEGER NOT NULL,
+    run_at INTEGER NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    claimed_by TEXT,
+    lease_until INTEGER,
+    acked_at INTEGER,
+    UNIQUE (queue, id)
+);
+CREATE INDEX IF NOT EXISTS messages_pending ON messages (queue, acked_at, priority DESC, seq);
+CREATE TABLE IF NOT EXISTS dead_letters (
+    seq INTEGER PRIMARY KEY,
+    queue TEXT NOT NULL,
+    id TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    error TEXT NOT NULL,
+    failed_at INTEGER NOT NULL
+);
+";
+
+/// Embedded SQLite database shared by all stages of the pipeline on one host.
+/// Acknowledged messages are kept, so state of tasks can be looked up later.
+#[derive(Clone)]
+pub struct Store {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Store {
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection> {
+            let conn = Connection::open(&path).context("Opening store")?;
+            // Stages of the pipeline are separate processes which write the store at once.
+            conn.busy_timeout(Duration::from_secs(5))
+                .context("Setting busy timeout")?;
+            conn.pragma_update(None, "journal_mode", "WAL")
+                .context("Enabling write-ahead log")?;
+            conn.execute_batch(SCHEMA).context("Creating schema")?;
+            Ok(conn)
+        })
+        .await
+        .context("Waiting for store")??;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn queue(&self, name: &'static str) -> Queue {
+        Queue {
+            store: self.clone(),
+            name,
+        }
+    }
+
+    async fn call<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            f(&mut conn)
+        })
+        .await
+        .context("Waiting for store")?
+    }
+}
+
+/// Message taken by a worker until it is acknowledged, released or its lease expires.
+#[derive(Debug)]
+pub struct Claimed<M> {
+    seq: i64,
+    /// Only the worker which holds the claim may finish it.
+    worker: String,
+    /// Number of claims of the message including this one.
+    pub attempt: u32,
+    pub message: M,
+}
+
+/// Where the message is in its queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Pending,
+    Claimed,
+    Acked,
+}
+
+impl State {
+    fn new(acked: bool, claimed: bool) -> Self {
+        match (acked, claimed) {
+            (true, _) => State::Acked,
+            (false, true) => State::Claimed,
+            (false, false) => State::Pending,
+        }
+    }
+}
+
+/// Named queue of the store.
+#[derive(Clone)]
+pub struct Queue {
+    store: Store,
+    name: &'static str,
+}
+
+impl Queue {
+    pub async fn send<M: Message>(&self, message: &M) -> Result<()> {
+        let row = Row::new(self.name, message)?;
+        let id = row.id;
+
+        self.store.call(move |conn| row.insert(conn)).await?;
+
+        trace!(queue = self.name, %id, "message sent");
+
+        Ok(())
+    }
+
+    /// Takes the next due message with the highest priority for the lease,
+    /// messages which lease expired are taken again.
+    /// Unreadable messages would be claimed forever, so they are moved into dead letters.
+    pub async fn claim<M: Message>(
+        &self,
+        worker: &str,
+        lease: Duration,
+    ) -> Result<Option<Claimed<M>>> {
+        let (name, worker) = (self.name, worker.to_string());
+        let lease = i64::try_from(lease.as_millis()).unwrap_or(i64::MAX);
+
+        let claimer = worker.clone();
+        let claimed = self
+            .store
+            .call(move |conn| {
+                let tx = conn
+                    .transaction_with_behavior(TransactionBehavior::Immediate)
+                    .context("Starting transaction")?;
+                let now = Utc::now().timestamp_millis();
+
+                loop {
+                    let Some((seq, payload, attempts)) = tx
+                        .query_row(
+                            "SELECT seq, payload, attempts FROM messages
+                             WHERE queue = ?1 AND acked_at IS NULL AND run_at <= ?2
+                               AND (lease_until IS NULL OR lease_until <= ?2)
+                             ORDER BY priority DESC, seq
+                             LIMIT 1",
+                            params![name, now],
+                            |row| {
+                                Ok((
+                                    row.get::<_, i64>(0)?,
+                                    row.get::<_, String>(1)?,
+                                    row.get::<_, u32>(2)?,
+                                ))
+                            },
+                        )
+                        .optional()
+                        .context("Selecting message")?
+                    else {
+                        tx.commit().context("Committing claim")?;
+                        return Ok(None);
+                    };
+
+                    let message = match from_json::<M>(payload.as_bytes()) {
+                        Ok(message) => message,
+                        Err(why) => {
+                            let why = why.context("Getting message from store");
+                            error!(queue = name, seq, "message moved into dead letters: {:?}", why);
+                            bury(&tx, seq, &format!("{:#}", why))?;
+                            continue;
+                        }
+                    };
+
+                    tx.execute(
+                        "UPDATE messages SET claimed_by = ?1, lease_until = ?2, attempts = attempts + 1
+                         WHERE seq = ?3",
+                        params![claimer, now.saturating_add(lease), seq],
+                    )
+                    .context("Claiming message")?;
+                    tx.commit().context("Committing claim")?;
+
+                    return Ok(Some((seq, message, attempts + 1)));
+                }
+            })
+            .await?;
+
+        Ok(claimed.map(|(seq, message, attempt)| {
+            trace!(queue = name, seq, attempt, "message claimed");
+            Claimed {
+                seq,
+                worker,
+                attempt,
+                message,
+            }
+        }))
+    }
+
+    /// Extends the lease of the claimed message, it fails once the claim is lost.
+    pub async fn renew<M>(&self, claimed: &Claimed<M>, lease: Duration) -> Result<()> {
+        let (seq, worker) = (claimed.seq, claimed.worker.clone());
+        let lease = i64::try_from(lease.as_millis()).unwrap_or(i64::MAX);
+
+        self.store
+            .call(move |conn| {
+                let renewed = conn
+                    .execute(
+                        "UPDATE messages SET lease_until = ?1
+                         WHERE seq = ?2 AND claimed_by = ?3 AND acked_at IS NULL",
+                        params![
+                            Utc::now().timestamp_millis().saturating_add(lease),
+                            seq,
+                            worker
+                        ],
+                    )
+                    .context("Renewing lease of message")?;
+                ensure!(renewed == 1, "Message is not claimed by {} anymore", worker);
+                Ok(())
+            })
+            .await?;
+
+        trace!(queue = self.name, seq, "lease renewed");
+
+        Ok(())
+    }
+
+    pub async fn ack<M>(&self, claimed: &Claimed<M>) -> Result<()> {
+        self.finish(claimed.seq, claimed.worker.clone(), None).await
+    }
+
+    /// Acknowledges the message and sends the next one in the same transaction,
+    /// so the next stage never misses it and never gets it twice.
+    pub async fn forward<M, N: Message>(
+        &self,
+        claimed: &Claimed<M>,
+        next: &Queue,
+        message: &N,
+    ) -> Result<()> {
+        let row = Row::new(next.name, message)?;
+        self.finish(claimed.seq, claimed.worker.clone(), Some(row))
+            .await
+    }
+
+    /// Acknowledges the message which can't be handled and keeps its copy with the error in dead letters.
+    pub async fn dead_letter<M>(&self, claimed: &Claimed<M>, error: &anyhow::Error) -> Result<()> {
+        let (seq, worker) = (claimed.seq, claimed.worker.clone());
+        let error = format!("{:#}", error);
+
+        self.store
+            .call(move |conn| {
+                let tx = conn
+                    .transaction_with_behavior(TransactionBehavior::Immediate)
+                    .context("Starting transaction")?;
+
+                let held = tx
+                    .query_row(
+                        "SELECT 1 FROM messages WHERE seq = ?1 AND claimed_by = ?2 AND acked_at IS NULL",
+                        params![seq, worker],
+                        |_| Ok(()),
+                    )
+                    .optional()
+                    .context("Selecting message")?
+                    .is_some();
+                ensure!(held, "Message is not claimed by {} anymore", worker);
+
+                bury(&tx, seq, &error)?;
+                tx.commit().context("Committing dead letter")
+            })
+            .await?;
+
+        warn!(queue = self.name, seq, "message moved into dead letters");
+
+        Ok(())
+    }
+
+    /// Returns the message into the queue, it is claimed again once the delay passes.
+    pub async fn release<M>(&self, claimed: &Claimed<M>, delay: Duration) -> Result<()> {
+        self.unclaim(claimed, delay, false).await
+    }
+
+    /// Returns the message into the queue like [`Queue::release`],
+    /// but the claim is not counted as an attempt.
+    pub async fn postpone<M>(&self, claimed: &Claimed<M>, delay: Duration) -> Result<()> {
+        self.unclaim(claimed, delay, true).await
+    }
+
+    /// Checks whether the message was ever sent into the queue.
+    pub async fn contains(&self, id: Uuid) -> Result<bool> {
+        let name = self.name;
+        self.store
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT 1 FROM messages WHERE queue = ?1 AND id = ?2",
+                    params![name, id.to_string()],
+                    |_| Ok(()),
+                )
+                .optional()
+                .map(|found| found.is_some())
+                .context("Selecting message")
+            })
+            .await
+    }
+
+    /// Dead letter of the message with the id.
+    pub async fn dead_lettered(&self, id: Uuid) -> Result<Option<DeadLetter>> {
+        let name = self.name;
+        let letters = self
+            .store
+            .call(move |conn| dead_letters(conn, name, Some(id)))
+            .await?;
+        Ok(letters.into_iter().next())
+    }
+
+    /// Dead letters of all messages of the queue.
+    pub async fn dead_letters(&self) -> Result<Vec<DeadLetter>> {
+        let name = self.name;
+        self.store
+            .call(move |conn| dead_letters(conn, name, None))
+            .await
+    }
+
+    /// The message with the id unless it was never sent into the queue.
+    pub async fn message<M: Message>(&self, id: Uuid) -> Result<Option<(M, State)>> {
+        let name = self.name;
+        let row = self
+            .store
+            .call(move |conn| {
+                let now = Utc::now().timestamp_millis();
+                conn.query_row(
+                    "SELECT payload, acked_at IS NOT NULL, COALESCE(lease_until > ?3, 0)
+                     FROM messages WHERE queue = ?1 AND id = ?2",
+                    params![name, id.to_string(), now],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, bool>(1)?,
+                            row.get::<_, bool>(2)?,
+                        ))
+                    },
+                )
+                .optional()
+                .context("Selecting message")
+            })
+            .await?;
+
+        match row {
+            Some((payload, acked, claimed)) => {
+                let message =
+                    from_json(payload.as_bytes()).context("Getting message from store")?;
+                Ok(Some((message, State::new(acked, claimed))))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// All messages of the queue including acknowledged ones.
+    pub async fn messages<M: Message>(&self) -> Result<Vec<(M, State)>> {
+        let name = self.name;
+        let rows = self
+            .store
+            .call(move |conn| {
+                let now = Utc::now().timestamp_millis();
+                let mut stmt = conn
+                    .prepare(
+                        "SELECT payload, acked_at IS NOT NULL, COALESCE(lease_until > ?2, 0)
+                         FROM messages WHERE queue = ?1 ORDER BY seq",
+                    )
+                    .context("Preparing statement")?;
+                let rows = stmt
+                    .query_map(params![name, now], |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, bool>(1)?,
+                            row.get::<_, bool>(2)?,
+                        ))
+                    })
+                    .context("Selecting messages")?
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Reading messages")?;
+                Ok(rows)
+            })
+            .await?;
+
+        let messages = rows
+            .into_iter()
+            .filter_map(|(payload, acked, claimed)| {
+                let message = from_json(payload.as_bytes())
+                    .inspect_err(|why| warn!(queue = name, "failed to read message: {:?}", why))
+                    .ok()?;
+                Some((message, State::new(acked, claimed)))
+            })
+            .collect();
+
+        Ok(messages)
+    }
+
+    async fn unclaim<M>(&self, claimed: &Claimed<M>, delay: Duration, uncount: bool) -> Result<()> {
+        let (seq, worker) = (claimed.seq, claimed.worker.clone());
+        let delay = i64::try_from(delay.as_millis()).unwrap_or(i64::MAX);
+
+        self.store
+            .call(move |conn| {
+                let released = conn
+                    .execute(
+                        "UPDATE messages SET claimed_by = NULL, lease_until = NULL, run_at = ?1,
+                           attempts = attempts - ?2
+                         WHERE seq = ?3 AND claimed_by = ?4 AND acked_at IS NULL",
+                        params![
+                            Utc::now().timestamp_millis().saturating_add(delay),
+                            uncount,
+                            seq,
+                            worker
+                        ],
+                    )
+                    .context("Releasing message")?;
+                // Message which lease expired may be claimed by another worker meanwhile.
+                ensure!(
+                    released == 1,
+                    "Message is not claimed by {} anymore",
+                    worker
+                );
+                Ok(())
+            })
+            .await?;
+
+        trace!(queue = self.name, seq, uncount, "message released");
+
+        Ok(())
+    }
+
+    /// Marks the message as acknowledged and inserts the next one if given.
+    /// Nothing is changed unless the worker still holds the claim.
+    async fn finish(&self, seq: i64, worker: String, next: Option<Row>) -> Result<()> {
+        self.store
+            .call(move |conn| {
+                let tx = conn
+                    .transaction_with_behavior(TransactionBehavior::Immediate)
+                    .context("Starting transaction")?;
+
+                let acked = tx
+                    .execute(
+                        "UPDATE messages SET claimed_by = NULL, lease_until = NULL, acked_at = ?1
+                         WHERE seq = ?2 AND claimed_by = ?3 AND acked_at IS NULL",
+                        params![Utc::now().timestamp_millis(), seq, worker],
+                    )
+                    .context("Acknowledging message")?;
+                ensure!(acked == 1, "Message is not claimed by {} anymore", worker);
+
+                if let Some(row) = next {
+                    row.insert(&tx)?;
+                }
+
+                tx.commit().context("Committing acknowledgement")
+            })
+            .await?;
+
+        trace!(queue = self.name, seq, "message acknowledged");
+
+        Ok(())
+    }
+}
+
+/// Acknowledges the message and keeps its copy with the error in dead letters.
+fn bury(conn: &Connection, seq: i64, error: &str) -> Result<()> {
+    let now = Utc::now().timestamp_millis();
+    conn.execute(
+        "INSERT INTO dead_letters (seq, queue, id, payload, attempts, error, failed_at)
+         SELECT seq, queue, id, payload, attempts, ?2, ?3 FROM messages WHERE seq = ?1",
+        params![seq, error, now],
+    )
+    .context("Inserting dead letter")?;
+    conn.execute(
+        "UPDATE messages SET claimed_by = NULL, lease_until = NULL, acked_at = ?1 WHERE seq = ?2",
+        params![now, seq],
+    )
+    .context("Acknowledging message")?;
+    Ok(())
+}
+
+fn dead_letters(conn: &Connection, queue: &str, id: Option<Uuid>) -> Result<Vec<DeadLetter>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, error, failed_at FROM dead_letters
+             WHERE queue = ?1 AND (?2 IS NULL OR id = ?2) ORDER BY seq",
+        )
+        .context("Preparing statement")?;
+    let rows = stmt
+        .query_map(params![queue, id.map(|id| id.to_string())], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })
+        .context("Selecting dead letters")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Reading dead letters")?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, error, failed_at)| {
+            Some(DeadLetter {
+                id: id.parse().ok()?,
+                error,
+                failed_at: DateTime::from_timestamp_millis(failed_at)?,
+            })
+        })
+        .collect())
+}
+
+/// Message which is not inserted yet.
+struct Row {
+    queue: &'static str,
+    id: Uuid,
+    priority: i32,
+    /// Milliseconds since epoch, messages without time are due at once.
+    run_at: i64,
+    payload: String,
+}
+
+impl Row {
+    fn new<M: Message>(queue: &'static str, message: &M) -> Result<Self> {
+        Ok(Self {
+            queue,
+            id: message.id(),
+            priority: message.priority(),
+            run_at: message
+                .run_at()
+                .map_or(0, |run_at| run_at.timestamp_millis()),
+            payload: String::from_utf8(to_json(message)?).context("Serializing message")?,
+        })
+    }
+
+    fn insert(self, conn: &Connection) -> Result<()> {
+        conn.execute(
+            "INSERT INTO messages (queue, id, priority, run_at, payload)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                self.queue,
+                self.id.to_string(),
+                self.priority,
+                self.run_at,
+                self.payload
+            ],
+        )
+        .map_err(|e| match e.sqlite_error_code() {
+            // Only the id is unique.
+            Some(ErrorCode::ConstraintViolation) => anyhow::Error::new(AlreadySent(self.id)),
+            _ => anyhow::Error::new(e),
+        })
+        .context("Inserting message")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::task, transport::TASKS, Task};
+    use chrono::TimeDelta;
+
+    const LEASE: Duration = Duration::from_secs(60);
+
+    async fn queue() -> Queue {
+        Store::open(":memory:").await.unwrap().queue(TASKS)
+    }
+
+    async fn claim(queue: &Queue, worker: &str, lease: Duration) -> Option<Claimed<Task>> {
+        queue.claim(worker, lease).await.unwrap()
+    }
+
+    async fn state(queue: &Queue, id: u128) -> Option<State> {
+        queue
+            .message::<Task>(Uuid::from_u128(id))
+            .await
+            .unwrap()
+            .map(|(_, state)| state)
+    }
+
+    #[tokio::test]
+    async fn claim_takes_due_messages_by_priority() {
+        let queue = queue().await;
+        let urgent = Task {
+            priority: 5,
+            ..task(2)
+        };
+        let held = Task {
+            priority: 10,
+            run_at: Some(Utc::now() + TimeDelta::hours(1)),
+            ..task(3)
+        };
+        for task in [task(1), urgent, held, task(4)] {
+            queue.send(&task).await.unwrap();
+        }
+
+        let mut claimed = Vec::new();
+        while let Some(message) = claim(&queue, "worker", LEASE).await {
+            assert_eq!(message.attempt, 1);
+            claimed.push(message.message.id);
+        }
+
+        assert_eq!(claimed, [2, 1, 4].map(Uuid::from_u128));
+        assert_eq!(state(&queue, 1).await, Some(State::Claimed));
+        assert_eq!(state(&queue, 3).await, Some(State::Pending));
+    }
+
+    #[tokio::test]
+    async fn send_rejects_duplicate_id() {
+        let queue = queue().await;
+
+        queue.send(&task(1)).await.unwrap();
+
+        let why = queue.send(&task(1)).await.unwrap_err();
+        assert!(why.is::<AlreadySent>());
+        assert!(queue.contains(Uuid::from_u128(1)).await.unwrap());
+        assert!(!queue.contains(Uuid::from_u128(2)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn expired_lease_is_claimed_again() {
+        let queue = queue().await;
+        queue.send(&task(1)).await.unwrap();
+
+        let stale = claim(&queue, "first", Duration::ZERO).await.unwrap();
+        let claimed = claim(&queue, "second", LEASE).await.unwrap();
+        assert_eq!(claimed.attempt, 2);
+        assert!(claim(&queue, "third", LEASE).await.is_none());
+
+        // Only the worker which holds the claim may finish it.
+        assert!(queue.ack(&stale).await.is_err());
+        assert!(queue.renew(&stale, LEASE).await.is_err());
+        assert!(queue.release(&stale, Duration::ZERO).await.is_err());
+        assert_eq!(state(&queue, 1).await, Some(State::Claimed));
+
+        queue.renew(&claimed, LEASE).await.unwrap();
+        queue.ack(&claimed).await.unwrap();
+        assert_eq!(state(&queue, 1).await, Some(State::Acked));
+        assert!(queue.ack(&claimed).await.is_err());
+        assert!(claim(&queue, "third", LEASE).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn release_counts_attempt_and_postpone_does_not() {
+        let queue = queue().await;
+        queue.send(&task(1)).await.unwrap();
+
+        let claimed = claim(&queue, "worker", LEASE).await.unwrap();
+        queue.release(&claimed, Duration::ZERO).await.unwrap();
+        assert_eq!(state(&queue, 1).await, Some(State::Pending));
+
+        let claimed = claim(&queue, "worker", LEASE).await.unwrap();
+        assert_eq!(claimed.attempt, 2);
+        queue.postpone(&claimed, Duration::ZERO).await.unwrap();
+
+        let claimed = claim(&queue, "worker", LEASE).await.unwrap();
+        assert_eq!(claimed.attempt, 2);
+    }
+
+    #[tokio::test]
+    async fn released_message_waits_for_delay() {
+        let queue = queue().await;
+        queue.send(&task(1)).await.unwrap();
+
+        let claimed = claim(&queue, "worker", LEASE).await.unwrap();
+        queue.release(&claimed, LEASE).await.unwrap();
+
+        assert!(claim(&queue, "worker", LEASE).await.is_none());
+        assert_eq!(state(&queue, 1).await, Some(State::Pending));
+    }
+
+    #[tokio::test]
+    async fn forward_acks_and_sends_next_message_at_once() {
+        let queue = queue().await;
+        let next = queue.store.queue("next");
+        queue.send(&task(1)).await.unwrap();
+
+        let claimed = claim(&queue, "worker", LEASE).await.unwrap();
+        queue.forward(&claimed, &next, &task(1)).await.unwrap();
+
+        assert_eq!(state(&queue, 1).await, Some(State::Acked));
+        assert_eq!(state(&next, 1).await, Some(State::Pending));
+        let forwarded = claim(&next, "worker", LEASE).await.unwrap();
+        assert_eq!(forwarded.message.id, Uuid::from_u128(1));
+    }
+
+    #[tokio::test]
+    async fn forward_of_lost_claim_sends_nothing() {
+        let queue = queue().await;
+        let next = queue.store.queue("next");
+        queue.send(&task(1)).await.unwrap();
+
+        let stale = claim(&queue, "first", Duration::ZERO).await.unwrap();
+        let _claimed = claim(&queue, "second", LEASE).await.unwrap();
+
+        assert!(queue.forward(&stale, &next, &task(1)).await.is_err());
+        assert!(!next.contains(Uuid::from_u128(1)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unreadable_message_is_moved_into_dead_letters() {
+        let queue = queue().await;
+        queue
+            .send(&Task {
+                priority: 5,
+                ..task(1)
+            })
+            .await
+            .unwrap();
+        queue.send(&task(2)).await.unwrap();
+        queue
+            .store
+            .call(|conn| {
+                conn.execute(
+                    "UPDATE messages SET payload = '{}' WHERE id = ?1",
+                    params![Uuid::from_u128(1).to_string()],
+                )
+                .context("Breaking message")
+            })
+            .await
+            .unwrap();
+
+        let claimed = claim(&queue, "worker", LEASE).await.unwrap();
+        assert_eq!(claimed.message.id, Uuid::from_u128(2));
+        assert!(claim(&queue, "worker", LEASE).await.is_none());
+
+        let (id, payload) = queue
+            .store
+            .call(|conn| {
+                conn.query_row("SELECT id, payload FROM dead_letters", [], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })
+                .context("Selecting dead letter")
+            })
+            .await
+            .unwrap();
+        assert_eq!(id, Uuid::from_u128(1).to_string());
+        assert_eq!(payload, "{}");
+    }
+}
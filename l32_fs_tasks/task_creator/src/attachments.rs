@@ -0,0 +1,78 @@
+use crate::error::Error;
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+use task::Task;
+use tokio::fs;
+use tracing::{error, trace};
+
+/// Sidecar files of attachments, kept in the output folder beside task files
+/// even if tasks are handed off through the store.
+pub struct Attachments {
+    tasks: PathBuf,
+}
+
+impl Attachments {
+    pub fn new(tasks: PathBuf) -> Self {
+        Self { tasks }
+    }
+
+    /// Writes content of attachments before the task is queued, so processor finds them.
+    /// Returns the folder of attachments unless the task has none.
+    pub async fn save(&self, task: &Task, contents: Vec<Vec<u8>>) -> Result<Option<PathBuf>> {
+        if task.attachments.is_empty() {
+            return Ok(None);
+        }
+
+        let dir = task.attachments_dir(&self.tasks);
+        let res = async {
+            fs::create_dir_all(&dir)
+                .await
+                .context("Creating attachments folder")?;
+
+            for (attachment, content) in task.attachments.iter().zip(contents) {
+                fs::write(dir.join(&attachment.name), content)
+                    .await
+                    .context("Writing attachment")?;
+            }
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(why) = res {
+            remove(&dir).await;
+            return Err(why);
+        }
+
+        trace!(id = %task.id, files = task.attachments.len(), "attachments saved");
+
+        Ok(Some(dir))
+    }
+}
+
+/// Removes attachments of the task which is not queued.
+pub async fn remove(dir: &Path) {
+    if let Err(why) = fs::remove_dir_all(dir).await {
+        error!("failed to remove attachments: {:?}", why);
+    }
+}
+
+/// Names of attachments become file names, so they can't point outside the folder.
+pub fn validate(task: &Task) -> Result<(), Error> {
+    let mut names = HashSet::new();
+    for attachment in &task.attachments {
+        let name = attachment.name.as_str();
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', '\0']) {
+            return Err(Error::BadRequest(
+                "attachment name must be a plain file name",
+            ));
+        }
+        if !names.insert(name) {
+            return Err(Error::BadRequest("attachment names must be unique"));
+        }
+    }
+    Ok(())
+}
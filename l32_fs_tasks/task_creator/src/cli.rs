@@ -0,0 +1,79 @@
+use clap::{value_parser, Parser};
+use std::{net::Ipv4Addr, path::PathBuf};
+use task::Encoding;
+
+#[derive(Parser)]
+pub struct Cli {
+    /// Listening IP
+    #[clap(short, long, default_value = "0.0.0.0", env = "WBTECH_L32_CREATOR_IP")]
+    pub ip: Ipv4Addr,
+
+    /// Listening port
+    #[clap(
+        short,
+        long,
+        value_parser = value_parser!(u16).range(1..),
+        default_value_t = 3000,
+        env = "WBTECH_L32_CREATOR_PORT"
+    )]
+    pub port: u16,
+
+    /// Folder to store created tasks and their attachments.
+    #[clap(long, default_value = "output", env = "WBTECH_L32_CREATOR_OUTPUT")]
+    pub output: PathBuf,
+
+    /// Kinds of tasks which are accepted, i.e. executors enabled in processor.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "noop",
+        env = "WBTECH_L32_CREATOR_KINDS"
+    )]
+    pub kinds: Vec<String>,
+
+    /// Format of task files in the output folder.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = Encoding::Json,
+        env = "WBTECH_L32_CREATOR_ENCODING"
+    )]
+    pub encoding: Encoding,
+
+    /// Compress task files with zstd.
+    #[clap(long, env = "WBTECH_L32_CREATOR_ZSTD")]
+    pub zstd: bool,
+
+    /// SQLite database to hand off tasks through instead of the output folder.
+    #[clap(long, env = "WBTECH_L32_CREATOR_STORE")]
+    pub store: Option<PathBuf>,
+
+    /// Capacity of queue of accepted tasks which are not saved yet.
+    #[clap(
+        long,
+        value_parser = value_parser!(u32).range(1..),
+        default_value_t = 1024,
+        env = "WBTECH_L32_CREATOR_QUEUE_SIZE"
+    )]
+    pub queue_size: u32,
+
+    /// Folder to keep queued tasks until they are saved, so restart doesn't lose them.
+    #[clap(long, env = "WBTECH_L32_CREATOR_SPOOL")]
+    pub spool: Option<PathBuf>,
+
+    /// Seconds which clients are asked to wait when the queue is full.
+    #[clap(long, default_value_t = 1, env = "WBTECH_L32_CREATOR_RETRY_AFTER")]
+    pub retry_after: u64,
+
+    /// Folder of tasks handled by processor, used to report state of tasks.
+    #[clap(long, env = "WBTECH_L32_CREATOR_RESULTS")]
+    pub results: Option<PathBuf>,
+
+    /// Journal file of logger, used to report state of tasks.
+    #[clap(long, env = "WBTECH_L32_CREATOR_JOURNAL")]
+    pub journal: Option<PathBuf>,
+
+    /// Dead-letter folder of processor, used to reject ids of failed tasks.
+    #[clap(long, env = "WBTECH_L32_CREATOR_DEAD_LETTER")]
+    pub dead_letter: Option<PathBuf>,
+}
@@ -0,0 +1,165 @@
+use crate::{error::Error, status::task_files};
+use anyhow::{Context, Result};
+use notifier::ARCHIVE_DIR;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Mutex,
+};
+use task::{Store, Task, TASKS};
+use tokio::fs;
+use tracing::{trace, warn};
+use uuid::Uuid;
+
+/// Dependencies of known tasks, used to reject tasks which close a cycle.
+pub struct Dependencies {
+    graph: Mutex<HashMap<Uuid, Vec<Uuid>>>,
+}
+
+impl Dependencies {
+    /// Collects dependencies of saved and spooled tasks.
+    pub async fn load(tasks: &Path, spool: Option<&Path>, store: Option<&Store>) -> Result<Self> {
+        let mut dirs = vec![tasks.to_path_buf(), tasks.join(ARCHIVE_DIR)];
+        dirs.extend(spool.map(Path::to_path_buf));
+
+        let mut graph = HashMap::new();
+        for dir in dirs {
+            for path in task_files(&dir).await? {
+                let content = fs::read(&path).await.context("Reading task file")?;
+                match task::decode::<Task>(&path, &content) {
+                    Ok(task) if !task.depends_on.is_empty() => {
+                        graph.insert(task.id, task.depends_on);
+                    }
+                    Ok(_) => {}
+                    Err(why) => warn!(?path, "failed to read task file: {:?}", why),
+                }
+            }
+        }
+
+        if let Some(store) = store {
+            for (task, _) in store.queue(TASKS).messages::<Task>().await? {
+                if !task.depends_on.is_empty() {
+                    graph.insert(task.id, task.depends_on);
+                }
+            }
+        }
+
+        trace!(tasks = graph.len(), "dependencies loaded");
+
+        Ok(Self {
+            graph: Mutex::new(graph),
+        })
+    }
+
+    /// Remembers dependencies of the task unless they lead back to it.
+    pub fn insert(&self, task: &Task) -> Result<(), Error> {
+        if task.depends_on.is_empty() {
+            return Ok(());
+        }
+
+        if task.depends_on.contains(&task.id) {
+            return Err(Error::BadRequest("task depends on itself"));
+        }
+
+        let mut graph = self.graph.lock().unwrap();
+
+        // Tasks which depend on the new one may be created earlier,
+        // so a cycle is closed if the new task is reachable from its dependencies.
+        let mut visited = HashSet::new();
+        let mut stack = task.depends_on.clone();
+        while let Some(id) = stack.pop() {
+            if id == task.id {
+                return Err(Error::BadRequest("depends_on forms a cycle"));
+            }
+            if visited.insert(id) {
+                stack.extend(graph.get(&id).into_iter().flatten().copied());
+            }
+        }
+
+        graph.insert(task.id, task.depends_on.clone());
+
+        Ok(())
+    }
+
+    /// Forgets dependencies of the task which is not accepted after all.
+    pub fn remove(&self, id: &Uuid) {
+        self.graph.lock().unwrap().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use task::test_util;
+
+    fn dependencies() -> Dependencies {
+        Dependencies {
+            graph: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn task(id: u128, depends_on: &[u128]) -> Task {
+        Task {
+            depends_on: depends_on.iter().copied().map(Uuid::from_u128).collect(),
+            ..test_util::task(id)
+        }
+    }
+
+    fn is_cycle(res: Result<(), Error>) -> bool {
+        matches!(res, Err(Error::BadRequest("depends_on forms a cycle")))
+    }
+
+    #[test]
+    fn insert_rejects_task_depending_on_itself() {
+        let dependencies = dependencies();
+
+        let res = dependencies.insert(&task(1, &[2, 1]));
+
+        assert!(matches!(
+            res,
+            Err(Error::BadRequest("task depends on itself"))
+        ));
+    }
+
+    #[test]
+    fn insert_rejects_task_closing_cycle() {
+        let dependencies = dependencies();
+        dependencies.insert(&task(2, &[1])).unwrap();
+        dependencies.insert(&task(3, &[2])).unwrap();
+
+        assert!(is_cycle(dependencies.insert(&task(1, &[3]))));
+        assert!(is_cycle(dependencies.insert(&task(2, &[3]))));
+    }
+
+    #[test]
+    fn insert_accepts_shared_dependencies() {
+        let dependencies = dependencies();
+        dependencies.insert(&task(2, &[1])).unwrap();
+        dependencies.insert(&task(3, &[1])).unwrap();
+
+        dependencies.insert(&task(4, &[2, 3])).unwrap();
+        dependencies.insert(&task(5, &[4, 1])).unwrap();
+        dependencies.insert(&task(6, &[])).unwrap();
+    }
+
+    #[test]
+    fn rejected_task_is_not_remembered() {
+        let dependencies = dependencies();
+        dependencies.insert(&task(2, &[1])).unwrap();
+        assert!(is_cycle(dependencies.insert(&task(1, &[2]))));
+
+        // Task 1 is unknown, so depending on it closes no cycle.
+        dependencies.insert(&task(3, &[1])).unwrap();
+    }
+
+    #[test]
+    fn removed_task_does_not_close_cycle() {
+        let dependencies = dependencies();
+        dependencies.insert(&task(2, &[1])).unwrap();
+        dependencies.insert(&task(3, &[2])).unwrap();
+
+        dependencies.remove(&Uuid::from_u128(2));
+
+        dependencies.insert(&task(1, &[3])).unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{error::Error, status::Status};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use task::{Attachment, RetryPolicy, Task};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct TaskDto {
+    pub id: Option<Uuid>,
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub title: String,
+    pub description: String,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentDto>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub complete_until: Option<DateTime<Utc>>,
+    pub retry: Option<RetryPolicy>,
+    #[serde(default)]
+    pub priority: i32,
+    pub run_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub depends_on: Vec<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct AttachmentDto {
+    pub name: String,
+    /// Content of the file encoded with base64.
+    pub content: String,
+}
+
+impl TaskDto {
+    /// Splits the task from content of its attachments.
+    pub fn into_task(self) -> Result<(Task, Vec<Vec<u8>>), Error> {
+        let mut attachments = Vec::new();
+        let mut contents = Vec::new();
+        for attachment in &self.attachments {
+            let content = BASE64_STANDARD
+                .decode(&attachment.content)
+                .map_err(|_| Error::BadRequest("attachment content must be base64"))?;
+            attachments.push(Attachment {
+                name: attachment.name.clone(),
+                size: content.len() as u64,
+            });
+            contents.push(content);
+        }
+
+        let mut task = Task::from(self);
+        task.attachments = attachments;
+
+        Ok((task, contents))
+    }
+}
+
+impl From<TaskDto> for Task {
+    fn from(task_dto: TaskDto) -> Self {
+        Self {
+            id: task_dto.id.unwrap_or(Uuid::new_v4()),
+            kind: task_dto.kind.unwrap_or_else(Task::default_kind),
+            args: task_dto.args,
+            title: task_dto.title,
+            description: task_dto.description,
+            payload: task_dto.payload,
+            attachments: Vec::new(),
+            created_at: task_dto.created_at.unwrap_or(Utc::now()),
+            complete_until: task_dto.complete_until,
+            retry: task_dto.retry,
+            priority: task_dto.priority,
+            run_at: task_dto.run_at,
+            depends_on: task_dto.depends_on,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Accepted {
+    pub id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct TasksQuery {
+    pub status: Option<Status>,
+}
+
+#[derive(Serialize)]
+pub struct ItemReport {
+    /// Position of the item in the batch.
+    pub index: usize,
+    #[serde(flatten)]
+    pub result: ItemResult,
+}
+
+impl ItemReport {
+    pub fn new(index: usize, res: Result<Uuid, String>) -> Self {
+        let result = match res {
+            Ok(id) => ItemResult::Accepted { id },
+            Err(error) => ItemResult::Rejected { error },
+        };
+        Self { index, result }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ItemResult {
+    Accepted { id: Uuid },
+    Rejected { error: String },
+}
@@ -0,0 +1,71 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::IntoResponse,
+};
+use thiserror::Error;
+use tracing::error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Input validation error: {0}")]
+    BadRequest(&'static str),
+
+    #[error("Queue is full, please try later")]
+    QueueFull { retry_after: u64 },
+
+    #[error("Please try later")]
+    QueueClosed { retry_after: u64 },
+
+    #[error("Conflict: {0}")]
+    Conflict(&'static str),
+
+    #[error("{0} not found")]
+    NotFound(&'static str),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl Error {
+    /// Status and message for the client, details of internal errors are only logged.
+    pub fn reply(&self) -> (StatusCode, String) {
+        match self {
+            Error::BadRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            Error::QueueFull { .. } => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
+            Error::QueueClosed { .. } => {
+                error!("failed sending task to worker: queue is closed");
+                (StatusCode::SERVICE_UNAVAILABLE, self.to_string())
+            }
+            Error::Conflict(_) => (StatusCode::CONFLICT, self.to_string()),
+            Error::NotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
+            Error::Other(err) => {
+                error!("failed handling request: {:?}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    String::from("Something went wrong"),
+                )
+            }
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let retry_after = match self {
+            Error::QueueFull { retry_after } | Error::QueueClosed { retry_after } => {
+                Some(retry_after)
+            }
+            _ => None,
+        };
+
+        let mut response = self.reply().into_response();
+
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+
+        response
+    }
+}
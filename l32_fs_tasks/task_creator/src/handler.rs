@@ -0,0 +1,244 @@
+use crate::{
+    attachments,
+    dto::{Accepted, ItemReport, TaskDto, TasksQuery},
+    error::Error,
+    state::AppState,
+    status::TaskState,
+    telemetry,
+};
+use anyhow::Context;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use futures::StreamExt;
+use serde_json::value::RawValue;
+use task::Task;
+use tracing::{debug, trace};
+use uuid::Uuid;
+
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+#[axum::debug_handler]
+pub async fn create_task(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(paylaod): Json<TaskDto>,
+) -> Result<(StatusCode, Json<Accepted>), Error> {
+    let (task, contents) = paylaod.into_task().inspect_err(telemetry::record_reject)?;
+
+    let key = headers
+        .get(IDEMPOTENCY_KEY)
+        .map(|value| value.to_str())
+        .transpose()
+        .map_err(|_| Error::BadRequest("Idempotency-Key must be visible ASCII"))
+        .inspect_err(telemetry::record_reject)?;
+
+    let id = match key {
+        Some(key) => {
+            state
+                .idempotency
+                .resolve(key.to_string(), accept(&state, task, contents))
+                .await?
+        }
+        None => accept(&state, task, contents).await?,
+    };
+
+    Ok((StatusCode::ACCEPTED, Json(Accepted { id })))
+}
+
+/// Accepts JSON array or NDJSON stream of tasks, each one is validated and queued on its own.
+pub async fn create_tasks(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<Vec<ItemReport>>, Error> {
+    let is_ndjson = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| NDJSON_TYPES.iter().any(|ty| value.starts_with(ty)));
+
+    let mut reports = Vec::new();
+    let mut report = |res: Result<Uuid, String>| {
+        reports.push(ItemReport::new(reports.len(), res));
+    };
+
+    if is_ndjson {
+        let mut stream = body.into_data_stream();
+        let mut buffer = Vec::new();
+
+        loop {
+            let chunk = stream.next().await.transpose().context("Reading body")?;
+            let eof = chunk.is_none();
+            buffer.extend_from_slice(&chunk.unwrap_or_default());
+
+            let mut lines = buffer.split(|byte| *byte == b'\n').collect::<Vec<_>>();
+            // Last line may be incomplete until the end of stream.
+            let rest = if eof {
+                Vec::new()
+            } else {
+                lines.pop().unwrap_or_default().to_vec()
+            };
+            // Stream may be endless, but a single task is limited like the whole array.
+            if rest.len() > MAX_BATCH_BYTES {
+                return Err(Error::BadRequest("line is too large"));
+            }
+            for line in lines
+                .into_iter()
+                .filter(|line| !line.trim_ascii().is_empty())
+            {
+                report(accept_json(&state, line).await);
+            }
+            buffer = rest;
+
+            if eof {
+                break;
+            }
+        }
+    } else {
+        let body = to_bytes(body, MAX_BATCH_BYTES)
+            .await
+            .map_err(|_| Error::BadRequest("body is too large"))?;
+        let items = serde_json::from_slice::<Vec<Box<RawValue>>>(&body)
+            .map_err(|_| Error::BadRequest("expected JSON array of tasks"))?;
+        for item in items {
+            report(accept_json(&state, item.get().as_bytes()).await);
+        }
+    }
+
+    debug!(items = reports.len(), "batch handled");
+
+    Ok(Json(reports))
+}
+
+const NDJSON_TYPES: [&str; 3] = [
+    "application/x-ndjson",
+    "application/jsonl",
+    "application/ndjson",
+];
+
+const MAX_BATCH_BYTES: usize = 64 * 1024 * 1024;
+
+async fn accept_json(state: &AppState, json: &[u8]) -> Result<Uuid, String> {
+    let task_dto = serde_json::from_slice::<TaskDto>(json)
+        .inspect_err(|_| telemetry::record_reject(&Error::BadRequest("invalid task")))
+        .map_err(|e| e.to_string())?;
+    // Items are reported like responses of the single task endpoint.
+    let (task, contents) = task_dto
+        .into_task()
+        .inspect_err(telemetry::record_reject)
+        .map_err(|e| e.reply().1)?;
+    accept(state, task, contents).await.map_err(|e| e.reply().1)
+}
+
+async fn accept(state: &AppState, task: Task, contents: Vec<Vec<u8>>) -> Result<Uuid, Error> {
+    let res = validate_and_queue(state, task, contents).await;
+    telemetry::record_accept(&res);
+    res
+}
+
+/// Validates the task and sends it to worker.
+async fn validate_and_queue(
+    AppState {
+        queue,
+        queued,
+        lookup,
+        dependencies,
+        attachments,
+        kinds,
+        ..
+    }: &AppState,
+    task: Task,
+    contents: Vec<Vec<u8>>,
+) -> Result<Uuid, Error> {
+    debug!(?task);
+
+    if !kinds.contains(&task.kind) {
+        return Err(Error::BadRequest("task kind is not allowed"));
+    }
+
+    if task
+        .complete_until
+        .is_some_and(|time| time < task.created_at)
+    {
+        return Err(Error::BadRequest(
+            "complete_until happens earlier than created_at",
+        ));
+    }
+
+    if task
+        .run_at
+        .zip(task.complete_until)
+        .is_some_and(|(run_at, complete_until)| complete_until < run_at)
+    {
+        return Err(Error::BadRequest(
+            "complete_until happens earlier than run_at",
+        ));
+    }
+
+    if task.retry.is_some_and(|retry| retry.max_attempts == 0) {
+        return Err(Error::BadRequest("retry.max_attempts must be positive"));
+    }
+
+    attachments::validate(&task)?;
+
+    let id = task.id;
+    if lookup.is_created(id).await? || !queued.insert(id) {
+        return Err(Error::Conflict("task with the same id already exists"));
+    }
+
+    if let Err(why) = dependencies.insert(&task) {
+        queued.remove(&id);
+        return Err(why);
+    }
+
+    let res = match attachments.save(&task, contents).await {
+        Ok(dir) => queue.push(task).await.map_err(|why| (why, dir)),
+        Err(why) => Err((why.into(), None)),
+    };
+    if let Err((why, dir)) = res {
+        queued.remove(&id);
+        dependencies.remove(&id);
+        if let Some(dir) = dir {
+            attachments::remove(&dir).await;
+        }
+        return Err(why);
+    }
+    trace!("task was sent to worker");
+
+    Ok(id)
+}
+
+pub async fn get_task(
+    State(AppState { queued, lookup, .. }): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<TaskState>, Error> {
+    lookup
+        .get(id, queued.contains(&id))
+        .await?
+        .map(Json)
+        .ok_or(Error::NotFound("Task"))
+}
+
+pub async fn list_tasks(
+    State(AppState { queued, lookup, .. }): State<AppState>,
+    Query(TasksQuery { status }): Query<TasksQuery>,
+) -> Result<Json<Vec<TaskState>>, Error> {
+    let mut states = lookup
+        .scan(queued.ids())
+        .await?
+        .into_values()
+        .filter(|state| match status {
+            Some(status) => state.status == status,
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    states.sort_by_key(|state| state.id);
+    Ok(Json(states))
+}
+
+pub async fn get_metrics(State(AppState { metrics, .. }): State<AppState>) -> String {
+    metrics.render()
+}
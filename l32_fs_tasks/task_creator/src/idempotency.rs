@@ -0,0 +1,126 @@
+use crate::error::Error;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{self, Arc},
+};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+use tracing::{error, trace};
+use uuid::Uuid;
+
+/// File in the output folder which keeps idempotency keys, one JSON record per line.
+const JOURNAL_FILE: &str = ".idempotency-keys";
+
+#[derive(Deserialize, Serialize)]
+struct Record {
+    key: String,
+    id: Uuid,
+}
+
+/// Task id of the key, it's empty until the task is accepted.
+type Slot = Arc<Mutex<Option<Uuid>>>;
+
+/// Ids of tasks created by requests with `Idempotency-Key` header.
+pub struct IdempotencyKeys {
+    /// Each key is locked on its own, so requests with other keys don't wait.
+    keys: sync::Mutex<HashMap<String, Slot>>,
+    journal: PathBuf,
+}
+
+impl IdempotencyKeys {
+    pub async fn load(dir: &Path) -> Result<Self> {
+        let journal = dir.join(JOURNAL_FILE);
+
+        let keys = match fs::read_to_string(&journal).await {
+            Ok(content) => content
+                .lines()
+                .filter_map(|line| serde_json::from_str::<Record>(line).ok())
+                .map(|Record { key, id }| (key, Arc::new(Mutex::new(Some(id)))))
+                .collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e).context("Reading idempotency keys"),
+        };
+
+        trace!(keys = keys.len(), "idempotency keys loaded");
+
+        Ok(Self {
+            keys: sync::Mutex::new(keys),
+            journal,
+        })
+    }
+
+    /// Returns id of the task created for the key before,
+    /// otherwise accepts a new task and remembers its id.
+    pub async fn resolve(
+        &self,
+        key: String,
+        accept: impl Future<Output = Result<Uuid, Error>>,
+    ) -> Result<Uuid, Error> {
+        let slot = self
+            .keys
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let mut guard = slot.lock().await;
+
+        if let Some(id) = *guard {
+            trace!(key, %id, "task was created before");
+            return Ok(id);
+        }
+
+        let id = match accept.await {
+            Ok(id) => id,
+            Err(why) => {
+                drop(guard);
+                self.forget(&key, slot);
+                return Err(why);
+            }
+        };
+
+        // Task is already accepted, so failure only makes the key short-lived.
+        if let Err(why) = self.persist(&key, id).await {
+            error!("failed to persist idempotency key: {:?}", why);
+        }
+        *guard = Some(id);
+
+        Ok(id)
+    }
+
+    /// Drops the key without task unless other request waits for it.
+    fn forget(&self, key: &str, slot: Slot) {
+        let mut keys = self.keys.lock().unwrap();
+        // Slots are cloned only under the map lock, so the count is exact here.
+        if Arc::strong_count(&slot) == 2 && slot.try_lock().is_ok_and(|id| id.is_none()) {
+            keys.remove(key);
+        }
+    }
+
+    async fn persist(&self, key: &str, id: Uuid) -> Result<()> {
+        let mut line = serde_json::to_vec(&Record {
+            key: key.to_string(),
+            id,
+        })
+        .context("Serializing idempotency key")?;
+        line.push(b'\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.journal)
+            .await
+            .context("Opening idempotency keys")?
+            .write_all(&line)
+            .await
+            .context("Writing idempotency key")
+    }
+}
@@ -1,26 +1,38 @@
+mod attachments;
+mod cli;
+mod dependencies;
+mod dto;
+mod error;
+mod handler;
+mod idempotency;
+mod state;
+mod status;
+mod telemetry;
+mod worker;
+
 use anyhow::Context;
-use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Json, Router};
-use chrono::{DateTime, Utc};
-use clap::{value_parser, Parser};
-use serde::Deserialize;
-use std::{
-    env,
-    net::{Ipv4Addr, SocketAddr},
-    path::{Path, PathBuf},
-    sync::Arc,
+use attachments::Attachments;
+use axum::{
+    routing::{get, post},
+    Router,
 };
-use task::Task;
-use thiserror::Error;
+use clap::Parser;
+use cli::Cli;
+use dependencies::Dependencies;
+use futures::future;
+use handler::{create_task, create_tasks, get_metrics, get_task, list_tasks};
+use idempotency::IdempotencyKeys;
+use state::{AppState, Queued};
+use status::Lookup;
+use std::{env, net::SocketAddr, sync::Arc};
+use task::{Codec, Store, TaskTransport, TASKS};
 use tokio::{
-    fs::File,
-    io::AsyncWriteExt,
     net::TcpListener,
-    sync::mpsc::{self, UnboundedSender},
+    signal::{self, unix::SignalKind},
 };
 use tower_http::trace::TraceLayer;
-use tracing::{debug, error, info, trace};
+use tracing::{error, info};
 use tracing_subscriber::EnvFilter;
-use uuid::Uuid;
 
 #[tokio::main]
 async fn main() {
@@ -51,64 +63,50 @@ fn setup_tracing() {
         .init();
 }
 
-#[derive(Debug, Error)]
-enum Error {
-    #[error("Input validation error: {0}")]
-    BadRequest(&'static str),
-
-    #[error("Please try later")]
-    QueueWorker(#[from] mpsc::error::SendError<Task>),
-}
-
-impl IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        match self {
-            Error::BadRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
-            Error::QueueWorker(ref err) => {
-                error!("failed sending task to worker: {:?}", err);
-                (StatusCode::SERVICE_UNAVAILABLE, self.to_string())
-            }
-        }
-        .into_response()
-    }
-}
-
-#[derive(Parser)]
-struct Cli {
-    /// Listening IP
-    #[clap(short, long, default_value = "0.0.0.0", env = "WBTECH_L32_CREATOR_IP")]
-    ip: Ipv4Addr,
-
-    /// Listening port
-    #[clap(
-        short,
-        long,
-        value_parser = value_parser!(u16).range(1..),
-        default_value_t = 3000,
-        env = "WBTECH_L32_CREATOR_PORT"
-    )]
-    port: u16,
-
-    /// Folder to store created tasks.
-    #[clap(long, default_value = "output", env = "WBTECH_L32_CREATOR_OUTPUT")]
-    output: PathBuf,
-}
-
 async fn run() -> Result<(), anyhow::Error> {
-    let Cli { ip, port, output } = Cli::try_parse()?;
-
-    let worker_tx = {
-        let (tx, mut rx) = mpsc::unbounded_channel();
-        tokio::spawn(async move {
-            while let Some(task) = rx.recv().await {
-                trace!(?task, "worker: got a new task");
-                if let Err(why) = save(task, &output).await {
-                    error!("failed to save task: {:?}", why);
-                }
-            }
-        });
-        tx
+    let Cli {
+        ip,
+        port,
+        output,
+        kinds,
+        encoding,
+        zstd,
+        store,
+        queue_size,
+        spool,
+        retry_after,
+        results,
+        journal,
+        dead_letter,
+    } = Cli::try_parse()?;
+
+    let metrics = telemetry::install()?;
+    let queued = Queued::default();
+    let idempotency = IdempotencyKeys::load(&output)
+        .await
+        .context("Loading idempotency keys")?;
+    let store = match store {
+        Some(path) => Some(Store::open(path).await.context("Opening store")?),
+        None => None,
+    };
+    let dependencies = Dependencies::load(&output, spool.as_deref(), store.as_ref())
+        .await
+        .context("Loading dependencies")?;
+    let lookup = Lookup::new(output.clone(), results, journal, dead_letter, store.clone());
+    let attachments = Attachments::new(output.clone());
+    let transport = match store {
+        Some(store) => TaskTransport::Sqlite(store.queue(TASKS)),
+        None => TaskTransport::Dir(output, Codec { encoding, zstd }),
     };
+    let (queue, worker) = worker::spawn(
+        transport,
+        spool,
+        queue_size as usize,
+        retry_after,
+        queued.clone(),
+    )
+    .await
+    .context("Spawning worker")?;
 
     let listener = {
         let addr = SocketAddr::from((ip, port));
@@ -117,90 +115,54 @@ async fn run() -> Result<(), anyhow::Error> {
 
     let app = Router::new()
         .route("/create_task", post(create_task))
+        .route("/create_tasks", post(create_tasks))
+        .route("/tasks", get(list_tasks))
+        .route("/tasks/:id", get(get_task))
+        .route("/metrics", get(get_metrics))
         .layer(TraceLayer::new_for_http())
-        .with_state(AppState::new(worker_tx));
+        .with_state(AppState {
+            queue: Arc::new(queue),
+            queued,
+            lookup: Arc::new(lookup),
+            idempotency: Arc::new(idempotency),
+            dependencies: Arc::new(dependencies),
+            attachments: Arc::new(attachments),
+            kinds: Arc::new(kinds.into_iter().collect()),
+            metrics,
+        });
 
     info!("start listening on {:?}:{}", ip, port);
-    axum::serve(listener, app).await.context("Running server")
-}
-
-async fn save(task: Task, path: impl AsRef<Path>) -> Result<(), anyhow::Error> {
-    debug!(?task, "saving task");
-
-    let mut file = {
-        let filename = task.id.to_string();
-        let mut path = path.as_ref().join(&filename);
-        path.set_extension("json");
-        File::create_new(&path)
-            .await
-            .inspect(|_| trace!(file = path.to_string_lossy().as_ref(), "task file created"))
-            .context("Creating task file")?
-    };
-
-    let content = serde_json::to_vec(&task)
-        .inspect(|bytes| trace!(bytes = bytes.len(), "task serialized"))
-        .context("Serializing task")?;
-
-    file.write_all(&content)
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
         .await
-        .inspect(|_| trace!("written {} bytes", content.len()))
-        .context("Saving task into file")
-}
+        .context("Running server")?;
 
-#[derive(Deserialize)]
-struct TaskDto {
-    pub id: Option<Uuid>,
-    pub title: String,
-    pub description: String,
-    pub created_at: Option<DateTime<Utc>>,
-    pub complete_until: Option<DateTime<Utc>>,
+    // Queue is closed once the server drops its state, so the worker saves what is left.
+    info!("waiting for queued tasks to be saved");
+    worker.await.context("Waiting for worker")
 }
 
-impl From<TaskDto> for Task {
-    fn from(task_dto: TaskDto) -> Self {
-        Self {
-            id: task_dto.id.unwrap_or(Uuid::new_v4()),
-            title: task_dto.title,
-            description: task_dto.description,
-            created_at: task_dto.created_at.unwrap_or(Utc::now()),
-            complete_until: task_dto.complete_until,
+async fn shutdown_signal() {
+    let terminate = async {
+        match signal::unix::signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(why) => {
+                error!("failed to listen for SIGTERM: {:?}", why);
+                future::pending::<()>().await;
+            }
         }
-    }
-}
-
-#[axum::debug_handler]
-async fn create_task(
-    State(AppState { worker_tx }): State<AppState>,
-    Json(paylaod): Json<TaskDto>,
-) -> Result<StatusCode, Error> {
-    let task = Task::from(paylaod);
-    debug!(?task);
-
-    if task
-        .complete_until
-        .is_some_and(|time| time < task.created_at)
-    {
-        return Err(Error::BadRequest(
-            "complete_until happens earlier than created_at",
-        ));
-    }
-
-    worker_tx
-        .send(task)
-        .inspect(|_| trace!("task was sent to worker"))?;
-
-    Ok(StatusCode::ACCEPTED)
-}
-
-#[derive(Clone)]
-struct AppState {
-    worker_tx: Arc<UnboundedSender<Task>>,
-}
+    };
 
-impl AppState {
-    fn new(worker_tx: UnboundedSender<Task>) -> Self {
-        Self {
-            worker_tx: Arc::new(worker_tx),
+    tokio::select! {
+        res = signal::ctrl_c() => {
+            if let Err(why) = res {
+                error!("failed to listen for SIGINT: {:?}", why);
+            }
         }
+        _ = terminate => {}
     }
+
+    info!("shutdown signal received, stop accepting tasks");
 }
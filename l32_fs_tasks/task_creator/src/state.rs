@@ -0,0 +1,47 @@
+use crate::{
+    attachments::Attachments, dependencies::Dependencies, idempotency::IdempotencyKeys,
+    status::Lookup, worker::Queue,
+};
+use metrics_exporter_prometheus::PrometheusHandle;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub queue: Arc<Queue>,
+    /// Accepted tasks which are not saved yet.
+    pub queued: Queued,
+    pub lookup: Arc<Lookup>,
+    pub idempotency: Arc<IdempotencyKeys>,
+    pub dependencies: Arc<Dependencies>,
+    pub attachments: Arc<Attachments>,
+    /// Kinds of tasks which are accepted.
+    pub kinds: Arc<HashSet<String>>,
+    pub metrics: PrometheusHandle,
+}
+
+#[derive(Clone, Default)]
+pub struct Queued {
+    ids: Arc<Mutex<HashSet<Uuid>>>,
+}
+
+impl Queued {
+    pub fn insert(&self, id: Uuid) -> bool {
+        self.ids.lock().unwrap().insert(id)
+    }
+
+    pub fn remove(&self, id: &Uuid) -> bool {
+        self.ids.lock().unwrap().remove(id)
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.ids.lock().unwrap().contains(id)
+    }
+
+    pub fn ids(&self) -> Vec<Uuid> {
+        self.ids.lock().unwrap().iter().copied().collect()
+    }
+}
@@ -0,0 +1,504 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use notifier::ARCHIVE_DIR;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use task::{
+    Codec, CompletedTask, DeadLetter, Inbox, JournalIndex, Recorded, State, Store, Task,
+    TaskOutput, COMPLETED, EXTENSIONS, TASKS,
+};
+use tokio::fs;
+use tracing::{trace, warn};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Processing,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskState {
+    pub id: Uuid,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<TaskOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl TaskState {
+    fn new(id: Uuid, status: Status) -> Self {
+        Self {
+            id,
+            status,
+            output: None,
+            completed_at: None,
+        }
+    }
+}
+
+impl From<CompletedTask> for TaskState {
+    fn from(comp_task: CompletedTask) -> Self {
+        let status = if comp_task.output.is_ok() {
+            Status::Completed
+        } else {
+            Status::Failed
+        };
+
+        Self {
+            id: comp_task.task.id,
+            status,
+            output: Some(comp_task.output),
+            completed_at: Some(comp_task.completed_at),
+        }
+    }
+}
+
+impl From<DeadLetter> for TaskState {
+    fn from(letter: DeadLetter) -> Self {
+        Self {
+            id: letter.id,
+            status: Status::Failed,
+            output: Some(TaskOutput::error(letter.error)),
+            completed_at: Some(letter.failed_at),
+        }
+    }
+}
+
+impl From<Recorded> for Status {
+    fn from(recorded: Recorded) -> Self {
+        match recorded {
+            Recorded::Ok => Status::Completed,
+            Recorded::Failed => Status::Failed,
+        }
+    }
+}
+
+impl From<State> for Status {
+    fn from(state: State) -> Self {
+        match state {
+            State::Pending => Status::Pending,
+            State::Claimed | State::Acked => Status::Processing,
+        }
+    }
+}
+
+/// Learns state of tasks from folders and files of the pipeline.
+pub struct Lookup {
+    /// Created tasks, i.e. input of processor.
+    tasks: PathBuf,
+    /// Handled tasks, i.e. output of processor.
+    results: Option<PathBuf>,
+    /// Tasks recorded in the journal of logger, so requests don't read the whole journal.
+    index: Option<Arc<Mutex<JournalIndex>>>,
+    /// Tasks which processor gave up on.
+    dead_letter: Option<PathBuf>,
+    /// Queues of tasks which are handed off through the store.
+    store: Option<Store>,
+}
+
+impl Lookup {
+    pub fn new(
+        tasks: PathBuf,
+        results: Option<PathBuf>,
+        journal: Option<PathBuf>,
+        dead_letter: Option<PathBuf>,
+        store: Option<Store>,
+    ) -> Self {
+        Self {
+            tasks,
+            results,
+            index: journal.map(|journal| Arc::new(Mutex::new(JournalIndex::new(journal)))),
+            dead_letter,
+            store,
+        }
+    }
+
+    /// Checks whether task with the id was saved before at any stage of the pipeline.
+    pub async fn is_created(&self, id: Uuid) -> Result<bool> {
+        let mut dirs = vec![self.tasks.clone(), self.tasks.join(ARCHIVE_DIR)];
+        if let Some(results) = &self.results {
+            dirs.extend([results.clone(), results.join(ARCHIVE_DIR)]);
+        }
+        dirs.extend(self.dead_letter.clone());
+        for dir in dirs {
+            if message_file(&dir, id).await?.is_some() {
+                return Ok(true);
+            }
+        }
+
+        if let Some(store) = &self.store {
+            for queue in [TASKS, COMPLETED] {
+                if store.queue(queue).contains(id).await? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(self.recorded(id).await?.is_some())
+    }
+
+    /// Failed state of the task which processor gave up on.
+    async fn dead_lettered(&self, id: Uuid) -> Result<Option<TaskState>> {
+        if let Some(dir) = &self.dead_letter {
+            if let Some(path) = message_file(dir, id).await? {
+                return Ok(Some(dead_file_state(&path, id).await));
+            }
+        }
+
+        if let Some(store) = &self.store {
+            if let Some(letter) = store.queue(TASKS).dead_lettered(id).await? {
+                return Ok(Some(letter.into()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Failed states of all tasks which processor gave up on.
+    async fn dead_letters(&self) -> Result<Vec<TaskState>> {
+        let mut states = Vec::new();
+
+        if let Some(dir) = &self.dead_letter {
+            for path in task_files(dir).await? {
+                let Some(id) = task::file_id(&path) else {
+                    continue;
+                };
+                states.push(dead_file_state(&path, id).await);
+            }
+        }
+
+        if let Some(store) = &self.store {
+            for letter in store.queue(TASKS).dead_letters().await? {
+                states.push(letter.into());
+            }
+        }
+
+        Ok(states)
+    }
+
+    /// Status of the task recorded in the journal.
+    async fn recorded(&self, id: Uuid) -> Result<Option<Status>> {
+        let recorded = self.with_journal(move |index| index.get(id)).await?;
+        Ok(recorded.flatten().map(Status::from))
+    }
+
+    /// All tasks recorded in the journal.
+    async fn records(&self) -> Result<Vec<(Uuid, Status)>> {
+        let records = self
+            .with_journal(|index| {
+                index
+                    .records()
+                    .map(|(id, recorded)| (id, recorded.into()))
+                    .collect()
+            })
+            .await?;
+        Ok(records.unwrap_or_default())
+    }
+
+    /// Looks into the journal index once lines appended since the last call are read.
+    async fn with_journal<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&JournalIndex) -> T + Send + 'static,
+    ) -> Result<Option<T>> {
+        let Some(index) = self.index.clone() else {
+            return Ok(None);
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let mut index = index.lock().unwrap();
+            index.refresh().context("Refreshing journal index")?;
+            Ok(Some(f(&index)))
+        })
+        .await
+        .context("Waiting for journal index")?
+    }
+
+    /// State of the task which is looked up by its id, the latest stage of the pipeline first.
+    pub async fn get(&self, id: Uuid, queued: bool) -> Result<Option<TaskState>> {
+        if let Some(results) = &self.results {
+            for dir in [results.clone(), results.join(ARCHIVE_DIR)] {
+                if let Some(path) = message_file(&dir, id).await? {
+                    let comp_task = read(&path).await?;
+                    return Ok(Some(comp_task.into()));
+                }
+            }
+        }
+
+        if let Some(store) = &self.store {
+            let completed = store.queue(COMPLETED);
+            if let Some((comp_task, _)) = completed.message::<CompletedTask>(id).await? {
+                return Ok(Some(comp_task.into()));
+            }
+        }
+
+        if let Some(status) = self.recorded(id).await? {
+            return Ok(Some(TaskState::new(id, status)));
+        }
+
+        // Dead letter is the only record of the task which used up its attempts.
+        if let Some(state) = self.dead_lettered(id).await? {
+            return Ok(Some(state));
+        }
+
+        if let Some(store) = &self.store {
+            if let Some((_, state)) = store.queue(TASKS).message::<Task>(id).await? {
+                return Ok(Some(TaskState::new(id, state.into())));
+            }
+        }
+
+        for dir in [self.tasks.clone(), self.tasks.join(ARCHIVE_DIR)] {
+            if let Some(path) = message_file(&dir, id).await? {
+                return Ok(Some(TaskState::new(id, file_status(&path).await)));
+            }
+        }
+
+        Ok(queued.then(|| TaskState::new(id, Status::Pending)))
+    }
+
+    /// Collects state of all known tasks, later stages of the pipeline win.
+    pub async fn scan(&self, queued: Vec<Uuid>) -> Result<HashMap<Uuid, TaskState>> {
+        let mut states = HashMap::new();
+
+        for id in queued {
+            states.insert(id, TaskState::new(id, Status::Pending));
+        }
+
+        for dir in [self.tasks.clone(), self.tasks.join(ARCHIVE_DIR)] {
+            for path in task_files(&dir).await? {
+                let Some(id) = task::file_id(&path) else {
+                    continue;
+                };
+                states.insert(id, TaskState::new(id, file_status(&path).await));
+            }
+        }
+
+        if let Some(store) = &self.store {
+            for (task, state) in store.queue(TASKS).messages::<Task>().await? {
+                states.insert(task.id, TaskState::new(task.id, state.into()));
+            }
+        }
+
+        for state in self.dead_letters().await? {
+            states.insert(state.id, state);
+        }
+
+        for (id, status) in self.records().await? {
+            states.insert(id, TaskState::new(id, status));
+        }
+
+        if let Some(results) = &self.results {
+            for dir in [results.clone(), results.join(ARCHIVE_DIR)] {
+                for path in task_files(&dir).await? {
+                    match read(&path).await {
+                        Ok(comp_task) => {
+                            states.insert(comp_task.task.id, comp_task.into());
+                        }
+                        Err(why) => warn!("failed to read comp task file: {:?}", why),
+                    }
+                }
+            }
+        }
+
+        if let Some(store) = &self.store {
+            for (comp_task, _) in store.queue(COMPLETED).messages::<CompletedTask>().await? {
+                states.insert(comp_task.task.id, comp_task.into());
+            }
+        }
+
+        trace!(tasks = states.len(), "state of tasks collected");
+
+        Ok(states)
+    }
+}
+
+/// Files of every codec in the folder.
+pub async fn task_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("Reading folder"),
+    };
+
+    let mut files = Vec::new();
+    while let Some(entry) = entries.next_entry().await.context("Reading folder entry")? {
+        let path = entry.path();
+        if Codec::of_path(&path).is_some() {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// File of the message with the id in any codec.
+async fn message_file(dir: &Path, id: Uuid) -> Result<Option<PathBuf>> {
+    for extension in EXTENSIONS {
+        let path = dir.join(format!("{}.{}", id, extension));
+        if fs::try_exists(&path).await.context("Checking file")? {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Processor keeps attempts and lease files beside task file which it handles.
+/// Later stages of the pipeline know the outcome of the done task better, so they are looked up first.
+async fn file_status(path: &Path) -> Status {
+    /// Lease of done task never expires, such task is not processed anymore.
+    #[derive(Deserialize)]
+    struct Lease {
+        until: Option<DateTime<Utc>>,
+    }
+
+    let mut lease = path.as_os_str().to_owned();
+    lease.push(".lease");
+    if let Ok(content) = fs::read(&lease).await {
+        match serde_json::from_slice::<Lease>(&content) {
+            // Task file is kept in place once it is consumed.
+            Ok(Lease { until: None }) => return Status::Completed,
+            Ok(Lease { until: Some(until) }) if until > Utc::now() => return Status::Processing,
+            // Task of the crashed processor is claimed again.
+            Ok(Lease { until: Some(_) }) => {}
+            // Unreadable lease is taken as held one.
+            Err(_) => return Status::Processing,
+        }
+    }
+
+    // Failed task waits for its next attempt.
+    let mut attempts = path.as_os_str().to_owned();
+    attempts.push(".attempts");
+    match fs::try_exists(&attempts).await {
+        Ok(true) => Status::Processing,
+        _ => Status::Pending,
+    }
+}
+
+/// Error of the dead letter is kept beside its file.
+async fn dead_file_state(path: &Path, id: Uuid) -> TaskState {
+    match Inbox::read_dead_letter(path, id).await {
+        Ok(letter) => letter.into(),
+        Err(why) => {
+            warn!(file = ?path, "failed to read dead letter: {:?}", why);
+            TaskState::new(id, Status::Failed)
+        }
+    }
+}
+
+async fn read(path: &Path) -> Result<CompletedTask> {
+    let content = fs::read(path).await.context("Reading comp task file")?;
+    task::decode(path, &content).context("Getting comp task from file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    async fn status(lease: Option<&str>, attempts: bool) -> Status {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("task.json");
+        fs::write(&path, "{}").await.unwrap();
+        if let Some(lease) = lease {
+            fs::write(dir.path().join("task.json.lease"), lease)
+                .await
+                .unwrap();
+        }
+        if attempts {
+            fs::write(dir.path().join("task.json.attempts"), "1")
+                .await
+                .unwrap();
+        }
+        file_status(&path).await
+    }
+
+    fn lease(until: Option<DateTime<Utc>>) -> String {
+        serde_json::json!({ "worker": "processor", "until": until }).to_string()
+    }
+
+    #[tokio::test]
+    async fn file_without_lease_is_pending() {
+        assert_eq!(status(None, false).await, Status::Pending);
+    }
+
+    #[tokio::test]
+    async fn file_with_attempts_is_processing() {
+        assert_eq!(status(None, true).await, Status::Processing);
+    }
+
+    #[tokio::test]
+    async fn held_lease_is_processing() {
+        let until = Utc::now() + TimeDelta::minutes(10);
+        assert_eq!(
+            status(Some(&lease(Some(until))), false).await,
+            Status::Processing
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_lease_is_pending() {
+        let until = Utc::now() - TimeDelta::minutes(10);
+        assert_eq!(
+            status(Some(&lease(Some(until))), false).await,
+            Status::Pending
+        );
+        assert_eq!(
+            status(Some(&lease(Some(until))), true).await,
+            Status::Processing
+        );
+    }
+
+    #[tokio::test]
+    async fn done_lease_is_completed() {
+        assert_eq!(status(Some(&lease(None)), false).await, Status::Completed);
+    }
+
+    #[tokio::test]
+    async fn unreadable_lease_is_processing() {
+        assert_eq!(status(Some("{"), false).await, Status::Processing);
+    }
+
+    #[tokio::test]
+    async fn dead_lettered_task_is_failed() {
+        let dir = tempfile::tempdir().unwrap();
+        let dead_letter = dir.path().join("dead-letter");
+        let id = Uuid::from_u128(1);
+        fs::create_dir(&dead_letter).await.unwrap();
+        fs::write(dead_letter.join(format!("{id}.json")), "{}")
+            .await
+            .unwrap();
+        fs::write(
+            dead_letter.join(format!("{id}.json.error")),
+            serde_json::json!({ "error": "failed", "attempts": 3, "failed_at": Utc::now() })
+                .to_string(),
+        )
+        .await
+        .unwrap();
+
+        let lookup = Lookup::new(
+            dir.path().join("input"),
+            None,
+            None,
+            Some(dead_letter),
+            None,
+        );
+
+        let state = lookup.get(id, false).await.unwrap().unwrap();
+        assert_eq!(state.status, Status::Failed);
+        assert!(state.output.is_some_and(|output| !output.is_ok()));
+        assert!(lookup.is_created(id).await.unwrap());
+        assert_eq!(
+            lookup.scan(Vec::new()).await.unwrap()[&id].status,
+            Status::Failed
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use crate::error::Error;
+use anyhow::Context;
+use metrics::{counter, describe_counter, describe_gauge};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use uuid::Uuid;
+
+pub const TASKS_ACCEPTED: &str = "tasks_accepted_total";
+pub const TASKS_REJECTED: &str = "tasks_rejected_total";
+pub const TASKS_SAVED: &str = "tasks_saved_total";
+pub const SAVE_FAILURES: &str = "task_save_failures_total";
+pub const QUEUE_DEPTH: &str = "task_queue_depth";
+
+/// Installs recorder, its handle renders metrics for `/metrics` route.
+pub fn install() -> Result<PrometheusHandle, anyhow::Error> {
+    let handle = PrometheusBuilder::new()
+        .install_recorder()
+        .context("Installing metrics recorder")?;
+
+    describe_counter!(TASKS_ACCEPTED, "Tasks accepted and queued for saving.");
+    describe_counter!(TASKS_REJECTED, "Tasks rejected, labeled by reason.");
+    describe_counter!(TASKS_SAVED, "Tasks saved into the output folder.");
+    describe_counter!(SAVE_FAILURES, "Tasks which failed to be saved.");
+    describe_gauge!(QUEUE_DEPTH, "Accepted tasks which are not saved yet.");
+
+    Ok(handle)
+}
+
+pub fn record_accept(res: &Result<Uuid, Error>) {
+    match res {
+        Ok(_) => counter!(TASKS_ACCEPTED).increment(1),
+        Err(why) => record_reject(why),
+    }
+}
+
+pub fn record_reject(why: &Error) {
+    let reason = match why {
+        Error::BadRequest(_) => "bad_request",
+        Error::QueueFull { .. } => "queue_full",
+        Error::QueueClosed { .. } => "queue_closed",
+        Error::Conflict(_) => "conflict",
+        Error::NotFound(_) => "not_found",
+        Error::Other(_) => "internal",
+    };
+    counter!(TASKS_REJECTED, "reason" => reason).increment(1);
+}
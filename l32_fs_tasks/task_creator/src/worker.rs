@@ -0,0 +1,161 @@
+use crate::{error::Error, state::Queued, telemetry};
+use anyhow::Context;
+use metrics::{counter, gauge};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+use task::{AlreadySent, Codec, Task, TaskTransport};
+use tokio::{
+    fs,
+    sync::mpsc::{self, error::TrySendError, Sender},
+    task::JoinHandle,
+};
+use tracing::{error, info, trace, warn};
+
+/// Bounded queue of accepted tasks,
+/// optionally backed by spool folder to survive restarts.
+pub struct Queue {
+    tx: Sender<Task>,
+    spool: Option<PathBuf>,
+    /// Seconds which clients are asked to wait when the queue is unavailable.
+    retry_after: u64,
+}
+
+impl Queue {
+    pub async fn push(&self, task: Task) -> Result<(), Error> {
+        let retry_after = self.retry_after;
+
+        if self.tx.capacity() == 0 {
+            return Err(Error::QueueFull { retry_after });
+        }
+
+        // Spool is read by creator only, so it is always JSON.
+        if let Some(spool) = &self.spool {
+            TaskTransport::Dir(spool.clone(), Codec::default())
+                .send(&task)
+                .await
+                .context("Spooling task")?;
+        }
+
+        let (task, err) = match self.tx.try_send(task) {
+            Ok(()) => {
+                gauge!(telemetry::QUEUE_DEPTH)
+                    .set((self.tx.max_capacity() - self.tx.capacity()) as f64);
+                return Ok(());
+            }
+            Err(TrySendError::Full(task)) => (task, Error::QueueFull { retry_after }),
+            Err(TrySendError::Closed(task)) => (task, Error::QueueClosed { retry_after }),
+        };
+
+        if let Some(spool) = &self.spool {
+            unspool(&task, spool).await;
+        }
+
+        Err(err)
+    }
+}
+
+/// Spawns worker which hands off accepted tasks to processor.
+/// The worker stops once all senders are dropped and the queue is drained.
+pub async fn spawn(
+    transport: TaskTransport,
+    spool: Option<PathBuf>,
+    capacity: usize,
+    retry_after: u64,
+    queued: Queued,
+) -> Result<(Queue, JoinHandle<()>), anyhow::Error> {
+    let (tx, mut rx) = mpsc::channel::<Task>(capacity);
+
+    if let Some(spool) = &spool {
+        fs::create_dir_all(spool)
+            .await
+            .context("Creating spool folder")?;
+
+        let tasks = spooled(spool).await.context("Reading spool folder")?;
+        info!(tasks = tasks.len(), "replaying spooled tasks");
+        // Replayed tasks are known like accepted ones until they are saved.
+        for task in &tasks {
+            queued.insert(task.id);
+        }
+
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            for task in tasks {
+                if tx.send(task).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let worker = {
+        let spool = spool.clone();
+        tokio::spawn(async move {
+            while let Some(task) = rx.recv().await {
+                trace!(?task, "worker: got a new task");
+                gauge!(telemetry::QUEUE_DEPTH).set(rx.len() as f64);
+                match transport.send(&task).await {
+                    Ok(()) => {
+                        counter!(telemetry::TASKS_SAVED).increment(1);
+                        if let Some(spool) = &spool {
+                            unspool(&task, spool).await;
+                        }
+                    }
+                    // Replayed task may be saved right before the restart, then it's delivered already.
+                    Err(why) if why.is::<AlreadySent>() => {
+                        warn!(id = %task.id, "task is saved already");
+                        if let Some(spool) = &spool {
+                            unspool(&task, spool).await;
+                        }
+                    }
+                    Err(why) => {
+                        counter!(telemetry::SAVE_FAILURES).increment(1);
+                        error!("failed to save task: {:?}", why);
+                    }
+                }
+                queued.remove(&task.id);
+            }
+            info!("worker: queue is drained");
+        })
+    };
+
+    let queue = Queue {
+        tx,
+        spool,
+        retry_after,
+    };
+
+    Ok((queue, worker))
+}
+
+async fn spooled(spool: &Path) -> Result<Vec<Task>, anyhow::Error> {
+    let mut entries = fs::read_dir(spool).await?;
+
+    let mut tasks = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension() != Some(OsStr::new("json")) {
+            continue;
+        }
+        let content = fs::read(&path).await.context("Reading spooled task")?;
+        match task::from_json::<Task>(&content) {
+            Ok(task) => tasks.push(task),
+            Err(why) => error!(
+                file = path.to_string_lossy().as_ref(),
+                "failed to read spooled task: {:?}", why
+            ),
+        }
+    }
+
+    tasks.sort_by_key(|task| task.created_at);
+
+    Ok(tasks)
+}
+
+async fn unspool(task: &Task, spool: &Path) {
+    let path = spool.join(format!("{}.json", task.id));
+    if let Err(why) = fs::remove_file(&path).await {
+        error!("failed to remove spooled task: {:?}", why);
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+use task::{rotated_files, CompletedTask, TaskResult};
+use tracing::trace;
+use uuid::Uuid;
+
+/// Line of the journal, one per completed task.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub task_id: Uuid,
+    pub kind: String,
+    pub title: String,
+    pub status: Status,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: DateTime<Utc>,
+    /// Milliseconds from creation to completion of the task.
+    pub duration_ms: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Failed,
+}
+
+impl From<CompletedTask> for Entry {
+    fn from(comp_task: CompletedTask) -> Self {
+        let CompletedTask {
+            task,
+            output,
+            completed_at,
+            ..
+        } = comp_task;
+
+        let (status, value, error) = match output.result {
+            TaskResult::Value(value) => (Status::Ok, value, None),
+            TaskResult::Error(error) => (Status::Failed, None, Some(error)),
+        };
+
+        Self {
+            task_id: task.id,
+            kind: task.kind,
+            title: task.title,
+            status,
+            created_at: task.created_at,
+            completed_at,
+            duration_ms: (completed_at - task.created_at).num_milliseconds(),
+            value,
+            error,
+        }
+    }
+}
+
+/// When the journal file is moved aside and a new one is started.
+#[derive(Debug, Clone, Copy)]
+pub enum Rotation {
+    Never,
+    Daily,
+    Hourly,
+    /// Once the file grows over the size in bytes.
+    Size(u64),
+}
+
+impl Rotation {
+    /// Suffix of the file rotated at the time, files of the same period share it.
+    fn suffix(&self, time: DateTime<Utc>) -> String {
+        match self {
+            Rotation::Daily => time.format("%Y-%m-%d").to_string(),
+            Rotation::Hourly => time.format("%Y-%m-%d-%H").to_string(),
+            Rotation::Never | Rotation::Size(_) => time.format("%Y-%m-%dT%H-%M-%S").to_string(),
+        }
+    }
+}
+
+/// Writes entries as JSON lines into the file of the folder,
+/// rotated files get suffix of their period, e.g. `processed-tasks.log.2024-10-17`.
+pub struct Journal {
+    dir: PathBuf,
+    file: PathBuf,
+    rotation: Rotation,
+    /// Number of rotated files to keep, all are kept if not set.
+    retention: Option<usize>,
+    gzip: bool,
+    writer: BufWriter<File>,
+    /// Period of entries of the current file.
+    period: String,
+    size: u64,
+}
+
+impl Journal {
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        file: impl Into<PathBuf>,
+        rotation: Rotation,
+        retention: Option<usize>,
+        gzip: bool,
+    ) -> Result<Self> {
+        let (dir, file) = (dir.into(), file.into());
+
+        fs::create_dir_all(&dir).context("Creating journal folder")?;
+
+        let path = dir.join(&file);
+        let (writer, size) = append(&path)?;
+
+        // Existing file belongs to period of its last entry.
+        let modified = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map(DateTime::<Utc>::from)
+            .unwrap_or_else(|_| Utc::now());
+
+        Ok(Self {
+            period: rotation.suffix(modified),
+            dir,
+            file,
+            rotation,
+            retention,
+            gzip,
+            writer,
+            size,
+        })
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry).context("Serializing journal entry")?;
+        line.push(b'\n');
+
+        let now = Utc::now();
+        let is_due = match self.rotation {
+            Rotation::Never => false,
+            Rotation::Daily | Rotation::Hourly => self.rotation.suffix(now) != self.period,
+            Rotation::Size(max) => self.size > 0 && self.size + line.len() as u64 > max,
+        };
+        if is_due {
+            self.rotate(now).context("Rotating journal")?;
+        }
+
+        self.writer
+            .write_all(&line)
+            .and_then(|()| self.writer.flush())
+            .context("Writing journal entry")?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self, now: DateTime<Utc>) -> Result<()> {
+        self.writer.flush().context("Flushing journal")?;
+
+        let path = self.dir.join(&self.file);
+        let suffix = match self.rotation {
+            Rotation::Size(_) => self.rotation.suffix(now),
+            _ => self.period.clone(),
+        };
+        let rotated = self.free_name(&suffix);
+        fs::rename(&path, &rotated).context("Moving journal aside")?;
+        trace!(file = ?rotated, "journal rotated");
+
+        (self.writer, self.size) = append(&path)?;
+        self.period = self.rotation.suffix(now);
+
+        if self.gzip {
+            compress(&rotated).context("Compressing rotated journal")?;
+        }
+
+        if let Some(retention) = self.retention {
+            self.prune(retention).context("Removing old journals")?;
+        }
+
+        Ok(())
+    }
+
+    /// Name of rotated file which is not taken yet, rotating twice a period adds a counter.
+    fn free_name(&self, suffix: &str) -> PathBuf {
+        let base = format!("{}.{}", self.file.to_string_lossy(), suffix);
+        let is_free = |name: &str| {
+            !self.dir.join(name).exists() && !self.dir.join(format!("{name}.gz")).exists()
+        };
+
+        let mut name = base.clone();
+        let mut counter = 0;
+        while !is_free(&name) {
+            counter += 1;
+            name = format!("{base}.{counter}");
+        }
+        self.dir.join(name)
+    }
+
+    fn prune(&self, retention: usize) -> Result<()> {
+        // Newest go first.
+        let rotated = rotated_files(&self.dir, &self.file)?;
+        for path in rotated.into_iter().rev().skip(retention) {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    return Err(e).context("Removing rotated journal")
+                }
+                _ => trace!(file = ?path, "rotated journal removed"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> Result<(BufWriter<File>, u64)> {
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("Opening journal")?;
+    let size = file.metadata().context("Getting journal size")?.len();
+    Ok((BufWriter::new(file), size))
+}
+
+fn compress(path: &Path) -> Result<()> {
+    let mut gz_path = path.as_os_str().to_os_string();
+    gz_path.push(".gz");
+
+    let mut reader = File::open(path).context("Opening rotated journal")?;
+    let writer = File::create_new(&gz_path).context("Creating compressed journal")?;
+    let mut encoder = GzEncoder::new(writer, Compression::default());
+    io::copy(&mut reader, &mut encoder).context("Compressing journal")?;
+    encoder
+        .finish()
+        .and_then(|file| file.sync_all())
+        .context("Finishing compressed journal")?;
+
+    fs::remove_file(path).context("Removing uncompressed journal")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    const FILE: &str = "processed-tasks.log";
+
+    fn entry(id: u128) -> Entry {
+        Entry {
+            task_id: Uuid::from_u128(id),
+            kind: "sleep".to_string(),
+            title: "title".to_string(),
+            status: Status::Ok,
+            created_at: Utc::now(),
+            completed_at: Utc::now(),
+            duration_ms: 0,
+            value: None,
+            error: None,
+        }
+    }
+
+    fn ids(contents: &str) -> Vec<Uuid> {
+        contents
+            .lines()
+            .map(|line| serde_json::from_str::<Entry>(line).unwrap().task_id)
+            .collect()
+    }
+
+    fn names(files: Vec<PathBuf>) -> Vec<String> {
+        let mut names = files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn size_rotation_moves_full_file_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut journal = Journal::open(dir.path(), FILE, Rotation::Size(1), None, false).unwrap();
+
+        for id in 1..=3 {
+            journal.write(&entry(id)).unwrap();
+        }
+
+        let rotated = rotated_files(dir.path(), Path::new(FILE)).unwrap();
+        assert_eq!(rotated.len(), 2);
+        let mut rotated_ids = rotated
+            .iter()
+            .flat_map(|path| ids(&fs::read_to_string(path).unwrap()))
+            .collect::<Vec<_>>();
+        rotated_ids.sort();
+        assert_eq!(rotated_ids, [Uuid::from_u128(1), Uuid::from_u128(2)]);
+
+        let live = fs::read_to_string(dir.path().join(FILE)).unwrap();
+        assert_eq!(ids(&live), [Uuid::from_u128(3)]);
+    }
+
+    #[test]
+    fn rotated_file_is_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut journal = Journal::open(dir.path(), FILE, Rotation::Size(1), None, true).unwrap();
+
+        journal.write(&entry(1)).unwrap();
+        journal.write(&entry(2)).unwrap();
+
+        let rotated = rotated_files(dir.path(), Path::new(FILE)).unwrap();
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(rotated[0].extension().unwrap(), "gz");
+
+        let mut contents = String::new();
+        GzDecoder::new(File::open(&rotated[0]).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(ids(&contents), [Uuid::from_u128(1)]);
+    }
+
+    #[test]
+    fn prune_keeps_newest_rotated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for suffix in [
+            "2024-10-16",
+            "2024-10-17",
+            "2024-10-17.1.gz",
+            "2024-10-17.2",
+            "2024-10-17.10.gz",
+        ] {
+            fs::write(dir.path().join(format!("{FILE}.{suffix}")), "").unwrap();
+        }
+        fs::write(dir.path().join("other.log.2024-10-18"), "").unwrap();
+
+        let journal = Journal::open(dir.path(), FILE, Rotation::Daily, Some(2), false).unwrap();
+        journal.prune(2).unwrap();
+
+        assert_eq!(
+            names(rotated_files(dir.path(), Path::new(FILE)).unwrap()),
+            [
+                "processed-tasks.log.2024-10-17.10.gz",
+                "processed-tasks.log.2024-10-17.2",
+            ]
+        );
+        assert!(dir.path().join("other.log.2024-10-18").exists());
+        assert!(dir.path().join(FILE).exists());
+    }
+
+    #[test]
+    fn prune_keeps_all_within_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        for suffix in ["2024-10-16", "2024-10-17"] {
+            fs::write(dir.path().join(format!("{FILE}.{suffix}")), "").unwrap();
+        }
+
+        let journal = Journal::open(dir.path(), FILE, Rotation::Daily, Some(5), false).unwrap();
+        journal.prune(5).unwrap();
+
+        assert_eq!(rotated_files(dir.path(), Path::new(FILE)).unwrap().len(), 2);
+    }
+}
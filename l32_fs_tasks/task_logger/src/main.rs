@@ -1,22 +1,52 @@
+mod journal;
+mod query;
+mod telemetry;
+
 use anyhow::{Context, Result};
-use clap::Parser;
-use futures::StreamExt;
-use notifier::try_watch;
+use clap::{value_parser, Parser, Subcommand, ValueEnum};
+use futures::{future, Future, StreamExt};
+use journal::{Entry, Journal, Rotation, Status};
+use metrics::{counter, histogram};
+use notifier::{
+    cli::{BacklogOrder, OnConsumed, Watcher},
+    Bookkeeper, Notifier, ARCHIVE_DIR,
+};
+use query::QueryArgs;
 use std::{
     env,
+    net::SocketAddr,
     path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
-use task::{CompletedTask, TaskOutput};
-use tokio::fs::{self};
-use tracing::{debug, error, trace};
+use task::{CompletedTask, Inbox, SchemaOf, Store, COMPLETED, EXTENSIONS};
+use tokio::{
+    signal::{self, unix::SignalKind},
+    sync::oneshot,
+    time,
+};
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 
+/// Completed tasks are only passed to log worker, so the lease is short.
+const CLAIM_LEASE: Duration = Duration::from_secs(60);
+
+/// Completed task for log worker, the sender is notified once its entry is written.
+type Logged = (CompletedTask, oneshot::Sender<()>);
+
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Folder of completed tasks.
     #[clap(short, long, default_value = "input", env = "WBTECH_L32_LOGGER_INPUT")]
     input: PathBuf,
 
+    /// SQLite database to claim completed tasks from instead of the input folder.
+    #[clap(long, env = "WBTECH_L32_LOGGER_STORE")]
+    store: Option<PathBuf>,
+
     /// Folder to store logging information.
     #[clap(
         short,
@@ -26,6 +56,62 @@ struct Cli {
     )]
     output: PathBuf,
 
+    /// Folder to move unreadable files of completed tasks into.
+    #[clap(
+        long,
+        default_value = "dead-letter",
+        env = "WBTECH_L32_LOGGER_DEAD_LETTER"
+    )]
+    dead_letter: PathBuf,
+
+    /// Order of files which already exist in the input folder at start.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = BacklogOrder::Mtime,
+        env = "WBTECH_L32_LOGGER_BACKLOG"
+    )]
+    backlog: BacklogOrder,
+
+    /// What to do with input files once they are handled.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = OnConsumed::Journal,
+        env = "WBTECH_L32_LOGGER_ON_CONSUMED"
+    )]
+    on_consumed: OnConsumed,
+
+    /// Watch subfolders of the input folder as well.
+    #[clap(long, env = "WBTECH_L32_LOGGER_RECURSIVE")]
+    recursive: bool,
+
+    /// How changes of the input folder are noticed.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = Watcher::Inotify,
+        env = "WBTECH_L32_LOGGER_WATCHER"
+    )]
+    watcher: Watcher,
+
+    /// Interval of polling the input folder or the store in milliseconds.
+    #[clap(
+        long,
+        value_parser = value_parser!(u64).range(1..),
+        default_value_t = 1000,
+        env = "WBTECH_L32_LOGGER_POLL_INTERVAL"
+    )]
+    poll_interval: u64,
+
+    /// Report a file once while its events keep coming within the period in milliseconds, off by default.
+    #[clap(
+        long,
+        value_parser = value_parser!(u64).range(1..),
+        env = "WBTECH_L32_LOGGER_DEBOUNCE"
+    )]
+    debounce: Option<u64>,
+
     /// Logging journal file name.
     #[clap(
         short,
@@ -34,6 +120,59 @@ struct Cli {
         env = "WBTECH_L32_LOGGER_FILE"
     )]
     file: PathBuf,
+
+    /// When the journal file is moved aside and a new one is started.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = RotationPolicy::Never,
+        env = "WBTECH_L32_LOGGER_ROTATION"
+    )]
+    rotation: RotationPolicy,
+
+    /// Size of the journal file in bytes which triggers `size` rotation.
+    #[clap(
+        long,
+        value_parser = value_parser!(u64).range(1..),
+        default_value_t = 10 * 1024 * 1024,
+        env = "WBTECH_L32_LOGGER_MAX_SIZE"
+    )]
+    max_size: u64,
+
+    /// Number of rotated journal files to keep, all are kept if not set.
+    #[clap(long, env = "WBTECH_L32_LOGGER_RETENTION")]
+    retention: Option<usize>,
+
+    /// Compress rotated journal files with gzip.
+    #[clap(long, env = "WBTECH_L32_LOGGER_GZIP")]
+    gzip: bool,
+
+    /// Address to serve Prometheus metrics on, e.g. `0.0.0.0:9101`.
+    #[clap(long, env = "WBTECH_L32_LOGGER_METRICS_ADDR")]
+    metrics_addr: Option<SocketAddr>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Filter journal entries and print statistics of their tasks.
+    Query(QueryArgs),
+    /// Print JSON Schema of the current version, to validate files before dropping them in the folder.
+    Schema {
+        #[clap(value_enum, default_value_t = SchemaOf::Completed)]
+        of: SchemaOf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RotationPolicy {
+    /// Single growing file
+    Never,
+    /// New file each day
+    Daily,
+    /// New file each hour
+    Hourly,
+    /// New file once the current one exceeds `--max-size`
+    Size,
 }
 
 #[tokio::main]
@@ -62,53 +201,175 @@ fn setup_tracing() {
 
 async fn run() -> Result<()> {
     let Cli {
+        command,
         input,
+        store,
         output,
+        dead_letter,
+        backlog,
+        on_consumed,
+        recursive,
+        watcher,
+        poll_interval,
+        debounce,
         file,
+        rotation,
+        max_size,
+        retention,
+        gzip,
+        metrics_addr,
     } = Cli::try_parse().context("Parsing args")?;
 
-    let (log_tx, log_rx) = std::sync::mpsc::channel::<CompletedTask>();
+    match command {
+        Some(Command::Query(args)) => return query::run(args).context("Querying journal"),
+        Some(Command::Schema { of }) => {
+            let json =
+                serde_json::to_string_pretty(&of.json_schema()).context("Serializing schema")?;
+            println!("{}", json);
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if let Some(addr) = metrics_addr {
+        telemetry::install(addr).context("Serving metrics")?;
+        info!(%addr, "serving metrics");
+    }
+
+    let rotation = match rotation {
+        RotationPolicy::Never => Rotation::Never,
+        RotationPolicy::Daily => Rotation::Daily,
+        RotationPolicy::Hourly => Rotation::Hourly,
+        RotationPolicy::Size => Rotation::Size(max_size),
+    };
+    let mut journal =
+        Journal::open(output, file, rotation, retention, gzip).context("Opening journal")?;
+
+    let (log_tx, log_rx) = std::sync::mpsc::channel::<Logged>();
 
     let log_worker = tokio::task::spawn_blocking(move || {
-        let appender = tracing_appender::rolling::never(output, file);
-        let subscriber = tracing_subscriber::fmt()
-            .with_writer(appender)
-            .with_ansi(false)
-            .finish();
-
-        tracing::subscriber::with_default(subscriber, || {
-            while let Some(task) = log_rx.iter().next() {
-                match task.output {
-                    TaskOutput::Value(_) => tracing::info!(?task),
-                    TaskOutput::Error(_) => tracing::error!(?task),
-                }
+        for (comp_task, written) in log_rx {
+            let entry = Entry::from(comp_task);
+            if let Err(why) = journal.write(&entry) {
+                error!("failed to write journal entry: {:?}", why);
+                counter!(telemetry::JOURNAL_FAILURES).increment(1);
+                continue;
             }
-        });
+            let _ = written.send(());
+
+            let status = match entry.status {
+                Status::Ok => "ok",
+                Status::Failed => "failed",
+            };
+            counter!(telemetry::TASKS_LOGGED, "status" => status).increment(1);
+            histogram!(telemetry::COMPLETION_SECONDS).record(entry.duration_ms as f64 / 1000.0);
+        }
     });
 
-    let mut notifier = try_watch(&input).await.context("Creating notifier")?;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    let store = match store {
+        Some(path) => Some(Store::open(path).await.context("Opening store")?),
+        None => None,
+    };
+    let inbox = Inbox::new(
+        store.as_ref().map(|store| store.queue(COMPLETED)),
+        Bookkeeper::new(&input, on_consumed.into()),
+        dead_letter,
+    );
+
+    if store.is_some() {
+        let res = claim(
+            &inbox,
+            &log_tx,
+            Duration::from_millis(poll_interval),
+            &mut shutdown,
+        )
+        .await;
+
+        drop(log_tx);
+        if let Err(why) = log_worker.await {
+            error!("failed to wait for cancellation of log worker: {:?}", why);
+        }
+
+        return res;
+    }
+
+    let mut builder = Notifier::builder(&input)
+        .backlog(backlog.backlog(created_at))
+        .backend(watcher.backend(Duration::from_millis(poll_interval)))
+        .recursive(recursive)
+        .extensions(EXTENSIONS)
+        .ignore(".*")
+        .ignore(ARCHIVE_DIR);
+    if let Some(debounce) = debounce {
+        builder = builder.debounce(Duration::from_millis(debounce));
+    }
+    let mut notifier = builder.watch().await.context("Creating notifier")?;
 
     let res = loop {
-        match notifier.next().await {
+        let next = tokio::select! {
+            next = notifier.next() => next,
+            _ = &mut shutdown => break Ok(()),
+        };
+
+        match next {
+            // Lost events do not stop logging, files are left in the folder for backlog of the next run.
+            Some(Err(notifier::Error::Overflow)) => {
+                warn!("notifier lost events, some task files are left until restart");
+            }
+            Some(Err(notifier::Error::Scan(why))) => {
+                warn!(
+                    "notifier failed to list folder, some task files are left until restart: {:?}",
+                    why
+                );
+            }
             Some(try_path) => {
                 let task_file = match try_path.context("Getting task file") {
                     Err(e) => break Err(e),
                     Ok(file) => file,
                 };
 
-                let task = match read(task_file).await.context("Getting task from file") {
-                    Err(e) => break Err(e),
-                    Ok(task) => task,
+                // Unreadable file is moved into dead-letter folder, otherwise backlog reports it on every start.
+                let received = match inbox.open::<CompletedTask>(&task_file).await {
+                    Ok(Some(received)) => received,
+                    Ok(None) => continue,
+                    Err(why) => {
+                        error!(file = ?task_file, "failed to open task file: {:?}", why);
+                        continue;
+                    }
                 };
+                debug!(comp_task = ?received.message(), "comp task extracted");
 
-                if let Err(e) = log_tx.send(task).context("Send to log worker") {
+                let (written_tx, written) = oneshot::channel();
+                if let Err(e) = log_tx
+                    .send((received.message().clone(), written_tx))
+                    .context("Send to log worker")
+                {
                     break Err(e);
                 }
+
+                // File which is not logged stays in place for backlog of the next run.
+                if written.await.is_err() {
+                    warn!(file = ?task_file, "completed task is not logged, its file is left in place");
+                    continue;
+                }
+                if let Err(why) = inbox.ack(&received).await {
+                    error!("failed to consume task file: {:?}", why);
+                }
             }
             None => break Ok(()),
         }
     };
 
+    if let Err(why) = notifier.close() {
+        error!("failed to close notifier: {:?}", why);
+    }
+
+    // Log worker writes what is left and flushes the journal once the channel is closed.
+    drop(log_tx);
+
     if let Err(why) = log_worker.await {
         error!("failed to wait for cancellation of log worker: {:?}", why);
     }
@@ -116,20 +377,80 @@ async fn run() -> Result<()> {
     res
 }
 
-async fn read(path: impl AsRef<Path>) -> Result<CompletedTask> {
-    trace!(
-        file = path.as_ref().to_string_lossy().as_ref(),
-        "reading task file"
-    );
+/// Creation time of the task which is completed in the file, for backlog ordered by it.
+fn created_at(path: &Path) -> Option<SystemTime> {
+    let content = std::fs::read(path).ok()?;
+    let comp_task = task::decode::<CompletedTask>(path, &content).ok()?;
+    Some(comp_task.task.created_at.into())
+}
+
+/// Passes completed tasks of the store to log worker until shutdown.
+async fn claim(
+    inbox: &Inbox,
+    log_tx: &std::sync::mpsc::Sender<Logged>,
+    poll_interval: Duration,
+    mut shutdown: impl Future<Output = ()> + Unpin,
+) -> Result<()> {
+    let worker = format!("logger-{}", std::process::id());
+
+    loop {
+        let claimed = tokio::select! {
+            claimed = inbox.claim::<CompletedTask>(&worker, CLAIM_LEASE) => claimed,
+            _ = &mut shutdown => return Ok(()),
+        };
+
+        let claimed = match claimed {
+            Ok(Some(claimed)) => claimed,
+            res => {
+                if let Err(why) = res {
+                    error!("failed to claim completed task: {:?}", why);
+                }
+                tokio::select! {
+                    _ = time::sleep(poll_interval) => continue,
+                    _ = &mut shutdown => return Ok(()),
+                }
+            }
+        };
+
+        debug!(comp_task = ?claimed.message(), "comp task claimed");
+
+        let (written_tx, written) = oneshot::channel();
+        log_tx
+            .send((claimed.message().clone(), written_tx))
+            .context("Send to log worker")?;
 
-    let content = fs::read(path)
-        .await
-        .inspect(|bytes| trace!("read {} bytes", bytes.len()))
-        .context("Reading task file")?;
+        // Task which is not acknowledged is claimed again once its lease expires.
+        if written.await.is_err() {
+            warn!("completed task is not logged, it is claimed again later");
+            continue;
+        }
+        if let Err(why) = inbox.ack(&claimed).await {
+            error!("failed to acknowledge completed task: {:?}", why);
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    let terminate = async {
+        match signal::unix::signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(why) => {
+                error!("failed to listen for SIGTERM: {:?}", why);
+                future::pending::<()>().await;
+            }
+        }
+    };
 
-    let comp_task = serde_json::from_slice(&content)
-        .inspect(|task| debug!(?task, "comp task extracted"))
-        .context("Getting task from file")?;
+    tokio::select! {
+        res = signal::ctrl_c() => {
+            if let Err(why) = res {
+                error!("failed to listen for SIGINT: {:?}", why);
+            }
+        }
+        _ = terminate => {}
+    }
 
-    Ok(comp_task)
+    info!("shutdown signal received, stop logging tasks");
 }
@@ -0,0 +1,313 @@
+use crate::journal::{Entry, Status};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Args, ValueEnum};
+use flate2::read::GzDecoder;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+use tracing::{debug, trace};
+
+/// Filters of journal entries, all given ones must match.
+#[derive(Args)]
+pub struct QueryArgs {
+    /// Folder of the journal.
+    #[clap(
+        short,
+        long,
+        default_value = "output",
+        env = "WBTECH_L32_LOGGER_OUTPUT"
+    )]
+    output: PathBuf,
+
+    /// Journal file name, rotated files are read as well.
+    #[clap(
+        short,
+        long,
+        default_value = "processed-tasks.log",
+        env = "WBTECH_L32_LOGGER_FILE"
+    )]
+    file: PathBuf,
+
+    /// Status of tasks.
+    #[clap(long, value_enum)]
+    status: Option<StatusFilter>,
+
+    /// Tasks completed at or after the time, given as RFC 3339 or `YYYY-MM-DD`.
+    #[clap(long, value_parser = parse_time)]
+    since: Option<DateTime<Utc>>,
+
+    /// Tasks completed before the time, given as RFC 3339 or `YYYY-MM-DD`.
+    #[clap(long, value_parser = parse_time)]
+    until: Option<DateTime<Utc>>,
+
+    /// Tasks which title contains the text, case is ignored.
+    #[clap(long)]
+    title: Option<String>,
+
+    /// Print matching entries before statistics.
+    #[clap(long)]
+    entries: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StatusFilter {
+    /// Completed successfully
+    Ok,
+    /// Completed with error
+    Failed,
+}
+
+fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.to_utc());
+    }
+
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        .map_err(|_| format!("expected RFC 3339 time or `YYYY-MM-DD`, got `{}`", s))
+}
+
+impl QueryArgs {
+    fn matches(&self, entry: &Entry) -> bool {
+        let status = self.status.map_or(true, |status| {
+            entry.status
+                == match status {
+                    StatusFilter::Ok => Status::Ok,
+                    StatusFilter::Failed => Status::Failed,
+                }
+        });
+        let since = self.since.map_or(true, |since| entry.completed_at >= since);
+        let until = self.until.map_or(true, |until| entry.completed_at < until);
+        let title = self.title.as_ref().map_or(true, |title| {
+            entry.title.to_lowercase().contains(&title.to_lowercase())
+        });
+
+        status && since && until && title
+    }
+}
+
+pub fn run(args: QueryArgs) -> Result<()> {
+    let files = task::journal_files(&args.output.join(&args.file))?;
+
+    let mut stats = Stats::default();
+    for file in files {
+        let reader = match open(&file) {
+            Ok(reader) => reader,
+            // Journal is not started yet or rotated file is just removed.
+            Err(why) => {
+                debug!(?file, "skipping journal: {:?}", why);
+                continue;
+            }
+        };
+
+        for line in reader.lines() {
+            let line = line.with_context(|| format!("Reading journal {:?}", file))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // Lines of older formats are counted, but not parsed.
+            let entry = match serde_json::from_str::<Entry>(&line) {
+                Ok(entry) => entry,
+                Err(why) => {
+                    trace!(?file, "skipping journal line: {}", why);
+                    stats.skipped += 1;
+                    continue;
+                }
+            };
+
+            if !args.matches(&entry) {
+                continue;
+            }
+
+            if args.entries {
+                println!("{}", line);
+            }
+            stats.add(&entry);
+        }
+    }
+
+    stats.print();
+
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path).context("Opening journal")?;
+    let reader: Box<dyn Read> = if path.extension().is_some_and(|ext| ext == "gz") {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+#[derive(Default)]
+struct Stats {
+    failed: usize,
+    /// Milliseconds from creation to completion of matching tasks.
+    durations: Vec<i64>,
+    skipped: usize,
+}
+
+impl Stats {
+    fn add(&mut self, entry: &Entry) {
+        if entry.status == Status::Failed {
+            self.failed += 1;
+        }
+        self.durations.push(entry.duration_ms);
+    }
+
+    fn print(mut self) {
+        let count = self.durations.len();
+        println!("count: {}", count);
+        println!("failed: {}", self.failed);
+
+        if count > 0 {
+            self.durations.sort_unstable();
+            let mean = self.durations.iter().sum::<i64>() / count as i64;
+
+            println!(
+                "failure rate: {:.2}%",
+                self.failed as f64 / count as f64 * 100.0
+            );
+            println!("latency mean: {} ms", mean);
+            println!("latency p50: {} ms", percentile(&self.durations, 50));
+            println!("latency p95: {} ms", percentile(&self.durations, 95));
+            println!("latency max: {} ms", self.durations[count - 1]);
+        }
+
+        if self.skipped > 0 {
+            println!("skipped lines: {}", self.skipped);
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[i64], p: usize) -> i64 {
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn args() -> QueryArgs {
+        QueryArgs {
+            output: PathBuf::from("output"),
+            file: PathBuf::from("processed-tasks.log"),
+            status: None,
+            since: None,
+            until: None,
+            title: None,
+            entries: false,
+        }
+    }
+
+    fn entry(status: Status, title: &str, completed_at: &str) -> Entry {
+        Entry {
+            task_id: Uuid::nil(),
+            kind: "sleep".to_string(),
+            title: title.to_string(),
+            status,
+            created_at: parse_time("2024-10-01").unwrap(),
+            completed_at: parse_time(completed_at).unwrap(),
+            duration_ms: 0,
+            value: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn percentile_takes_nearest_rank() {
+        let sorted = (1..=20).collect::<Vec<i64>>();
+
+        assert_eq!(percentile(&sorted, 0), 1);
+        assert_eq!(percentile(&sorted, 50), 10);
+        assert_eq!(percentile(&sorted, 95), 19);
+        assert_eq!(percentile(&sorted, 100), 20);
+    }
+
+    #[test]
+    fn percentile_of_few_values() {
+        assert_eq!(percentile(&[7], 50), 7);
+        assert_eq!(percentile(&[7], 95), 7);
+        assert_eq!(percentile(&[1, 2, 3], 50), 2);
+        assert_eq!(percentile(&[1, 2, 3], 95), 3);
+    }
+
+    #[test]
+    fn parse_time_accepts_date_and_rfc3339() {
+        assert_eq!(
+            parse_time("2024-10-17").unwrap(),
+            parse_time("2024-10-17T00:00:00Z").unwrap()
+        );
+        assert_eq!(
+            parse_time("2024-10-17T03:00:00+03:00").unwrap(),
+            parse_time("2024-10-17").unwrap()
+        );
+        assert!(parse_time("17.10.2024").is_err());
+    }
+
+    #[test]
+    fn no_filters_match_everything() {
+        let args = args();
+
+        assert!(args.matches(&entry(Status::Ok, "first", "2024-10-17")));
+        assert!(args.matches(&entry(Status::Failed, "second", "2024-10-18")));
+    }
+
+    #[test]
+    fn status_filter() {
+        let args = QueryArgs {
+            status: Some(StatusFilter::Failed),
+            ..args()
+        };
+
+        assert!(args.matches(&entry(Status::Failed, "title", "2024-10-17")));
+        assert!(!args.matches(&entry(Status::Ok, "title", "2024-10-17")));
+    }
+
+    #[test]
+    fn time_filters_include_since_and_exclude_until() {
+        let args = QueryArgs {
+            since: Some(parse_time("2024-10-17").unwrap()),
+            until: Some(parse_time("2024-10-18").unwrap()),
+            ..args()
+        };
+
+        assert!(!args.matches(&entry(Status::Ok, "title", "2024-10-16T23:59:59Z")));
+        assert!(args.matches(&entry(Status::Ok, "title", "2024-10-17")));
+        assert!(args.matches(&entry(Status::Ok, "title", "2024-10-17T23:59:59Z")));
+        assert!(!args.matches(&entry(Status::Ok, "title", "2024-10-18")));
+    }
+
+    #[test]
+    fn title_filter_ignores_case() {
+        let args = QueryArgs {
+            title: Some("REPORT".to_string()),
+            ..args()
+        };
+
+        assert!(args.matches(&entry(Status::Ok, "Weekly report", "2024-10-17")));
+        assert!(!args.matches(&entry(Status::Ok, "Weekly summary", "2024-10-17")));
+    }
+
+    #[test]
+    fn all_given_filters_must_match() {
+        let args = QueryArgs {
+            status: Some(StatusFilter::Ok),
+            title: Some("report".to_string()),
+            ..args()
+        };
+
+        assert!(args.matches(&entry(Status::Ok, "report", "2024-10-17")));
+        assert!(!args.matches(&entry(Status::Failed, "report", "2024-10-17")));
+        assert!(!args.matches(&entry(Status::Ok, "summary", "2024-10-17")));
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::{Context, Result};
+use metrics::{describe_counter, describe_histogram, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use std::net::SocketAddr;
+
+pub const TASKS_LOGGED: &str = "tasks_logged_total";
+pub const JOURNAL_FAILURES: &str = "journal_write_failures_total";
+pub const COMPLETION_SECONDS: &str = "task_completion_seconds";
+
+/// Buckets of time from creation to completion of tasks in seconds.
+const COMPLETION_BUCKETS: &[f64] = &[
+    0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0, 21600.0, 86400.0,
+];
+
+/// Serves metrics in Prometheus format on the address.
+pub fn install(addr: SocketAddr) -> Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .set_buckets_for_metric(
+            Matcher::Full(COMPLETION_SECONDS.to_string()),
+            COMPLETION_BUCKETS,
+        )
+        .context("Setting histogram buckets")?
+        .install()
+        .context("Installing metrics exporter")?;
+
+    describe_counter!(
+        TASKS_LOGGED,
+        "Completed tasks written into the journal, labeled by status."
+    );
+    describe_counter!(
+        JOURNAL_FAILURES,
+        "Completed tasks which failed to be written into the journal."
+    );
+    describe_histogram!(
+        COMPLETION_SECONDS,
+        Unit::Seconds,
+        "Time from creation to completion of logged tasks."
+    );
+    notifier::describe_metrics();
+
+    Ok(())
+}
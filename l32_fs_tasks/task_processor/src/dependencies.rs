@@ -0,0 +1,395 @@
+use anyhow::{Context, Result};
+use notifier::ARCHIVE_DIR;
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use task::{Codec, CompletedTask, JournalIndex, Recorded, Store, COMPLETED, EXTENSIONS, TASKS};
+use tokio::{fs, sync::watch};
+use tracing::{trace, warn};
+use uuid::Uuid;
+
+/// What is known about the task which others depend on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Settled {
+    Succeeded,
+    Failed,
+    /// Task was never created, or its outcome is lost.
+    Unknown,
+}
+
+impl From<bool> for Settled {
+    fn from(ok: bool) -> Self {
+        if ok {
+            Self::Succeeded
+        } else {
+            Self::Failed
+        }
+    }
+}
+
+impl From<Recorded> for Settled {
+    fn from(recorded: Recorded) -> Self {
+        match recorded {
+            Recorded::Ok => Self::Succeeded,
+            Recorded::Failed => Self::Failed,
+        }
+    }
+}
+
+/// Outcomes of settled tasks, by id of the task.
+pub type Completed = HashMap<Uuid, Settled>;
+
+/// Where outcomes of tasks are kept along the pipeline.
+pub struct Places {
+    /// Folder of created tasks, dependencies which are still there are pending.
+    pub tasks: PathBuf,
+    /// Where other processors save comp tasks as well.
+    pub output: PathBuf,
+    /// Journal of logger, which keeps outcomes of consumed comp tasks.
+    pub journal: Option<PathBuf>,
+    /// Folder of tasks which used up their attempts.
+    pub dead_letter: PathBuf,
+    pub store: Option<Store>,
+}
+
+/// Outcomes of completed tasks, which tasks with dependencies wait for.
+#[derive(Clone)]
+pub struct Outcomes {
+    completed: Arc<watch::Sender<Completed>>,
+    places: Arc<Places>,
+    journal: Option<Arc<Mutex<JournalIndex>>>,
+}
+
+impl Outcomes {
+    /// Collects comp tasks which are saved into the folder, archived or journaled by logger,
+    /// ones which are sent into the store, and tasks which are dead-lettered.
+    pub async fn load(places: Places) -> Result<Self> {
+        let outcomes = Self {
+            completed: Arc::new(watch::Sender::new(Completed::new())),
+            journal: places
+                .journal
+                .clone()
+                .map(|journal| Arc::new(Mutex::new(JournalIndex::new(journal)))),
+            places: Arc::new(places),
+        };
+
+        let mut completed = Completed::new();
+        let records = outcomes
+            .with_journal(|index| index.records().collect::<Vec<_>>())
+            .await?;
+        for (id, recorded) in records.unwrap_or_default() {
+            completed.insert(id, recorded.into());
+        }
+
+        for id in dead_files(&outcomes.places.dead_letter).await? {
+            completed.insert(id, Settled::Failed);
+        }
+
+        for dir in outcomes.output_dirs() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).context("Reading output folder"),
+            };
+
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .context("Reading output folder entry")?
+            {
+                let path = entry.path();
+                if Codec::of_path(&path).is_none() {
+                    continue;
+                }
+
+                let content = fs::read(&path).await.context("Reading comp task file")?;
+                match task::decode::<CompletedTask>(&path, &content) {
+                    Ok(comp_task) => {
+                        let ok = comp_task.output.is_ok();
+                        completed.insert(comp_task.task.id, ok.into());
+                    }
+                    Err(why) => warn!(?path, "failed to read comp task file: {:?}", why),
+                }
+            }
+        }
+
+        if let Some(store) = &outcomes.places.store {
+            for letter in store.queue(TASKS).dead_letters().await? {
+                completed.insert(letter.id, Settled::Failed);
+            }
+            for (comp_task, _) in store.queue(COMPLETED).messages::<CompletedTask>().await? {
+                let ok = comp_task.output.is_ok();
+                completed.insert(comp_task.task.id, ok.into());
+            }
+        }
+
+        trace!(tasks = completed.len(), "outcomes loaded");
+        outcomes.completed.send_replace(completed);
+
+        Ok(outcomes)
+    }
+
+    pub fn insert(&self, id: Uuid, ok: bool) {
+        self.settle(id, ok.into());
+    }
+
+    fn settle(&self, id: Uuid, settled: Settled) {
+        self.completed.send_modify(|completed| {
+            completed.insert(id, settled);
+        });
+    }
+
+    /// Looks up outcomes of the tasks which are not known yet, they may be completed by other processors.
+    /// Tasks which are found nowhere along the pipeline are settled as unknown, so dependents don't wait forever.
+    pub async fn refresh(&self, ids: &[Uuid]) -> Result<()> {
+        let unsettled = {
+            let completed = self.completed.borrow();
+            ids.iter()
+                .filter(|id| !completed.contains_key(id))
+                .copied()
+                .collect::<Vec<_>>()
+        };
+
+        for id in unsettled {
+            if let Some(settled) = self.lookup(id).await? {
+                trace!(%id, ?settled, "outcome found");
+                self.settle(id, settled);
+            } else if !self.is_pending(id).await? {
+                // Task may be settled while it was looked for.
+                let settled = self.lookup(id).await?.unwrap_or(Settled::Unknown);
+                trace!(%id, ?settled, "task is not pending");
+                self.settle(id, settled);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Comp task and dead letter files are named by id of the task, so only the files of the id are read.
+    async fn lookup(&self, id: Uuid) -> Result<Option<Settled>> {
+        for dir in self.output_dirs() {
+            for extension in EXTENSIONS {
+                let path = dir.join(format!("{}.{}", id, extension));
+                let content = match fs::read(&path).await {
+                    Ok(content) => content,
+                    Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e).context("Reading comp task file"),
+                };
+                let comp_task = task::decode::<CompletedTask>(&path, &content)
+                    .context("Getting comp task from file")?;
+                return Ok(Some(comp_task.output.is_ok().into()));
+            }
+        }
+
+        if let Some(store) = &self.places.store {
+            let completed = store.queue(COMPLETED);
+            if let Some((comp_task, _)) = completed.message::<CompletedTask>(id).await? {
+                return Ok(Some(comp_task.output.is_ok().into()));
+            }
+        }
+
+        if let Some(Some(recorded)) = self.with_journal(move |index| index.get(id)).await? {
+            return Ok(Some(recorded.into()));
+        }
+
+        if message_file(&self.places.dead_letter, id).await?.is_some() {
+            return Ok(Some(Settled::Failed));
+        }
+
+        if let Some(store) = &self.places.store {
+            if store.queue(TASKS).dead_lettered(id).await?.is_some() {
+                return Ok(Some(Settled::Failed));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Task is pending while its file or message waits to be processed.
+    async fn is_pending(&self, id: Uuid) -> Result<bool> {
+        let tasks = &self.places.tasks;
+        for dir in [tasks.clone(), tasks.join(ARCHIVE_DIR)] {
+            if message_file(&dir, id).await?.is_some() {
+                return Ok(true);
+            }
+        }
+
+        match &self.places.store {
+            Some(store) => store.queue(TASKS).contains(id).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Looks into the journal index once lines appended since the last call are read.
+    async fn with_journal<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&JournalIndex) -> T + Send + 'static,
+    ) -> Result<Option<T>> {
+        let Some(index) = self.journal.clone() else {
+            return Ok(None);
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let mut index = index.lock().unwrap();
+            index.refresh().context("Refreshing journal index")?;
+            Ok(Some(f(&index)))
+        })
+        .await
+        .context("Waiting for journal index")?
+    }
+
+    fn output_dirs(&self) -> [PathBuf; 2] {
+        let output = &self.places.output;
+        [output.clone(), output.join(ARCHIVE_DIR)]
+    }
+
+    /// Tasks may run once all dependencies succeeded, or fail once any of them failed.
+    pub fn is_settled(&self, depends_on: &[Uuid]) -> bool {
+        let completed = self.completed.borrow();
+        depends_on
+            .iter()
+            .all(|id| completed.get(id) == Some(&Settled::Succeeded))
+            || depends_on
+                .iter()
+                .any(|id| matches!(completed.get(id), Some(Settled::Failed | Settled::Unknown)))
+    }
+
+    /// The first dependency which failed or is unknown.
+    pub fn failed(&self, depends_on: &[Uuid]) -> Option<(Uuid, Settled)> {
+        let completed = self.completed.borrow();
+        depends_on.iter().find_map(|id| match completed.get(id) {
+            Some(settled @ (Settled::Failed | Settled::Unknown)) => Some((*id, *settled)),
+            _ => None,
+        })
+    }
+
+    /// Notifies about every settled task.
+    pub fn subscribe(&self) -> watch::Receiver<Completed> {
+        self.completed.subscribe()
+    }
+}
+
+/// File of the message with the id in any codec.
+async fn message_file(dir: &Path, id: Uuid) -> Result<Option<PathBuf>> {
+    for extension in EXTENSIONS {
+        let path = dir.join(format!("{}.{}", id, extension));
+        if fs::try_exists(&path).await.context("Checking file")? {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Ids of tasks which files are moved into dead-letter folder.
+async fn dead_files(dir: &Path) -> Result<Vec<Uuid>> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("Reading dead-letter folder"),
+    };
+
+    let mut ids = Vec::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context("Reading dead-letter folder entry")?
+    {
+        let path = entry.path();
+        if Codec::of_path(&path).is_none() {
+            continue;
+        }
+        let name = entry.file_name();
+        let stem = name.to_string_lossy();
+        if let Some(id) = stem.split('.').next().and_then(|s| s.parse().ok()) {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn outcomes(dir: &TempDir) -> Outcomes {
+        let places = Places {
+            tasks: dir.path().join("tasks"),
+            output: dir.path().join("output"),
+            journal: Some(dir.path().join("processed-tasks.log")),
+            dead_letter: dir.path().join("dead-letter"),
+            store: None,
+        };
+        for folder in [&places.tasks, &places.output, &places.dead_letter] {
+            fs::create_dir(folder).await.unwrap();
+        }
+        Outcomes::load(places).await.unwrap()
+    }
+
+    fn journal_line(id: Uuid, status: &str) -> String {
+        format!("{{\"task_id\":\"{id}\",\"status\":\"{status}\"}}\n")
+    }
+
+    #[tokio::test]
+    async fn journaled_dependency_is_settled() {
+        let dir = TempDir::new().unwrap();
+        let outcomes = outcomes(&dir).await;
+        let (ok, failed) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let lines = journal_line(ok, "ok") + &journal_line(failed, "failed");
+        fs::write(dir.path().join("processed-tasks.log"), lines)
+            .await
+            .unwrap();
+
+        outcomes.refresh(&[ok, failed]).await.unwrap();
+
+        assert!(outcomes.is_settled(&[ok]));
+        assert_eq!(outcomes.failed(&[ok]), None);
+        assert_eq!(
+            outcomes.failed(&[ok, failed]),
+            Some((failed, Settled::Failed))
+        );
+    }
+
+    #[tokio::test]
+    async fn dead_lettered_dependency_is_failed() {
+        let dir = TempDir::new().unwrap();
+        let outcomes = outcomes(&dir).await;
+        let id = Uuid::from_u128(1);
+        fs::write(dir.path().join(format!("dead-letter/{id}.json")), "{}")
+            .await
+            .unwrap();
+
+        outcomes.refresh(&[id]).await.unwrap();
+
+        assert_eq!(outcomes.failed(&[id]), Some((id, Settled::Failed)));
+    }
+
+    #[tokio::test]
+    async fn pending_dependency_is_awaited() {
+        let dir = TempDir::new().unwrap();
+        let outcomes = outcomes(&dir).await;
+        let id = Uuid::from_u128(1);
+        fs::write(dir.path().join(format!("tasks/{id}.json")), "{}")
+            .await
+            .unwrap();
+
+        outcomes.refresh(&[id]).await.unwrap();
+
+        assert!(!outcomes.is_settled(&[id]));
+    }
+
+    #[tokio::test]
+    async fn unknown_dependency_fails() {
+        let dir = TempDir::new().unwrap();
+        let outcomes = outcomes(&dir).await;
+        let id = Uuid::from_u128(1);
+
+        outcomes.refresh(&[id]).await.unwrap();
+
+        assert!(outcomes.is_settled(&[id]));
+        assert_eq!(outcomes.failed(&[id]), Some((id, Settled::Unknown)));
+    }
+}
@@ -0,0 +1,177 @@
+mod file;
+mod plugin;
+mod shell;
+
+pub use plugin::Plugin;
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use futures::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{ExitStatus, Output},
+    sync::Arc,
+};
+use task::{Task, TaskOutput};
+use thiserror::Error;
+use tokio::process::Command;
+
+/// Handles tasks of a particular kind.
+pub trait Executor: Send + Sync {
+    /// Runs the task and returns its output, attachments of the task are in the folder.
+    fn execute<'a>(
+        &'a self,
+        task: &'a Task,
+        attachments: &'a Path,
+    ) -> BoxFuture<'a, Result<TaskOutput>>;
+}
+
+/// Executors keyed by kind of task they handle.
+#[derive(Clone, Default)]
+pub struct Registry {
+    executors: HashMap<String, Arc<dyn Executor>>,
+}
+
+/// Built-in executors which run commands or touch files of the host,
+/// so they are registered only once enabled.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Builtin {
+    /// Shell command line
+    Shell,
+    /// SHA-256 of file
+    Checksum,
+    /// Gzip of file
+    Compress,
+    /// Copy of file
+    Copy,
+}
+
+impl Registry {
+    /// Registry of `noop` and the enabled built-ins.
+    pub fn with_builtins(enabled: &[Builtin]) -> Self {
+        let mut registry = Self::default();
+        registry.register(Task::DEFAULT_KIND, Noop);
+        for builtin in enabled {
+            match builtin {
+                Builtin::Shell => registry.register("shell", shell::Shell),
+                Builtin::Checksum => registry.register("checksum", file::Checksum),
+                Builtin::Compress => registry.register("compress", file::Compress),
+                Builtin::Copy => registry.register("copy", file::Copy),
+            };
+        }
+        registry
+    }
+
+    pub fn register(
+        &mut self,
+        kind: impl Into<String>,
+        executor: impl Executor + 'static,
+    ) -> &mut Self {
+        self.executors.insert(kind.into(), Arc::new(executor));
+        self
+    }
+
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn Executor>> {
+        self.executors.get(kind).cloned()
+    }
+}
+
+/// Does nothing and returns no value.
+struct Noop;
+
+impl Executor for Noop {
+    fn execute<'a>(
+        &'a self,
+        _task: &'a Task,
+        _attachments: &'a Path,
+    ) -> BoxFuture<'a, Result<TaskOutput>> {
+        Box::pin(async { Ok(TaskOutput::value(None)) })
+    }
+}
+
+fn arg<'a>(task: &'a Task, idx: usize, name: &str) -> Result<&'a str> {
+    task.args
+        .get(idx)
+        .map(String::as_str)
+        .ok_or_else(|| anyhow!("missing argument #{}: {}", idx, name))
+}
+
+/// Passes payload and attachments of the task to the program in environment variables.
+fn env(command: &mut Command, task: &Task, attachments: &Path) {
+    if !task.payload.is_null() {
+        command.env("TASK_PAYLOAD", task.payload.to_string());
+    }
+    if !task.attachments.is_empty() {
+        command.env("TASK_ATTACHMENTS", attachments);
+    }
+}
+
+/// Program exited with failure, what it printed is kept in the task output.
+#[derive(Debug, Error)]
+#[error("{status}: {}", .stderr.trim_end())]
+pub struct ProgramFailed {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Output value is the trimmed stdout of the program.
+fn stdout(output: Output) -> Result<TaskOutput> {
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+    if !output.status.success() {
+        return Err(ProgramFailed {
+            status: output.status,
+            stdout,
+            stderr,
+        }
+        .into());
+    }
+
+    let value = Some(stdout.trim_end().to_string()).filter(|value| !value.is_empty());
+
+    Ok(TaskOutput {
+        exit_code: output.status.code(),
+        stdout: Some(stdout),
+        stderr: Some(stderr).filter(|stderr| !stderr.is_empty()),
+        ..TaskOutput::value(value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use task::test_util::task;
+
+    #[tokio::test]
+    async fn registry_has_noop_and_enabled_builtins_only() {
+        let registry = Registry::with_builtins(&[Builtin::Shell, Builtin::Copy]);
+
+        assert!(registry.get("shell").is_some());
+        assert!(registry.get("copy").is_some());
+        assert!(registry.get("checksum").is_none());
+        assert!(registry.get("compress").is_none());
+
+        let noop = registry.get(Task::DEFAULT_KIND).unwrap();
+        let output = noop.execute(&task(1), Path::new("")).await.unwrap();
+        assert!(matches!(output.result, task::TaskResult::Value(None)));
+    }
+
+    #[test]
+    fn registered_executor_replaces_builtin() {
+        let mut registry = Registry::with_builtins(&[Builtin::Shell]);
+
+        registry.register("shell", Plugin::new("plugin"));
+
+        assert_eq!(registry.executors.len(), 2);
+    }
+
+    #[test]
+    fn missing_argument_is_named() {
+        let why = arg(&task(1), 1, "destination").unwrap_err();
+
+        assert_eq!(why.to_string(), "missing argument #1: destination");
+    }
+}
@@ -0,0 +1,220 @@
+use super::{arg, Executor};
+use anyhow::{Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+use task::{Task, TaskOutput};
+use tokio::{fs, task::spawn_blocking};
+use tracing::trace;
+
+/// Calculates SHA-256 of file given by the first argument.
+pub struct Checksum;
+
+impl Executor for Checksum {
+    fn execute<'a>(
+        &'a self,
+        task: &'a Task,
+        _attachments: &'a Path,
+    ) -> BoxFuture<'a, Result<TaskOutput>> {
+        Box::pin(async move {
+            let path = PathBuf::from(arg(task, 0, "file")?);
+            trace!(
+                file = path.to_string_lossy().as_ref(),
+                "calculating checksum"
+            );
+
+            let digest = spawn_blocking(move || -> Result<_> {
+                let mut file = File::open(&path).context("Opening file")?;
+                let mut hasher = Sha256::new();
+                io::copy(&mut file, &mut hasher).context("Reading file")?;
+                Ok(hasher.finalize())
+            })
+            .await??;
+
+            let hex = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+            Ok(TaskOutput::value(Some(hex)))
+        })
+    }
+}
+
+/// Compresses file given by the first argument with gzip.
+/// Destination is the second argument or source path with `.gz` suffix.
+pub struct Compress;
+
+impl Executor for Compress {
+    fn execute<'a>(
+        &'a self,
+        task: &'a Task,
+        _attachments: &'a Path,
+    ) -> BoxFuture<'a, Result<TaskOutput>> {
+        Box::pin(async move {
+            let src = PathBuf::from(arg(task, 0, "source")?);
+            let dst = match task.args.get(1) {
+                Some(dst) => PathBuf::from(dst),
+                None => {
+                    let mut dst = src.clone().into_os_string();
+                    dst.push(".gz");
+                    PathBuf::from(dst)
+                }
+            };
+            trace!(
+                src = src.to_string_lossy().as_ref(),
+                dst = dst.to_string_lossy().as_ref(),
+                "compressing file"
+            );
+
+            let dst = spawn_blocking(move || -> Result<_> {
+                let mut reader = File::open(&src)
+                    .map(BufReader::new)
+                    .context("Opening source file")?;
+                let mut encoder = File::create_new(&dst)
+                    .map(BufWriter::new)
+                    .map(|writer| GzEncoder::new(writer, Compression::default()))
+                    .context("Creating destination file")?;
+                io::copy(&mut reader, &mut encoder).context("Compressing file")?;
+                encoder.finish().context("Finishing compression")?;
+                Ok(dst)
+            })
+            .await??;
+
+            Ok(TaskOutput {
+                artifacts: vec![dst.clone()],
+                ..TaskOutput::value(Some(dst.to_string_lossy().into_owned()))
+            })
+        })
+    }
+}
+
+/// Copies file given by the first argument into the second one.
+pub struct Copy;
+
+impl Executor for Copy {
+    fn execute<'a>(
+        &'a self,
+        task: &'a Task,
+        _attachments: &'a Path,
+    ) -> BoxFuture<'a, Result<TaskOutput>> {
+        Box::pin(async move {
+            let src = arg(task, 0, "source")?;
+            let dst = arg(task, 1, "destination")?;
+            trace!(src, dst, "copying file");
+
+            let bytes = fs::copy(src, dst).await.context("Copying file")?;
+
+            Ok(TaskOutput {
+                artifacts: vec![PathBuf::from(dst)],
+                ..TaskOutput::value(Some(bytes.to_string()))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use task::test_util;
+    use tempfile::TempDir;
+
+    fn task(args: &[&Path]) -> Task {
+        Task {
+            args: args
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            ..test_util::task(1)
+        }
+    }
+
+    fn value(output: &TaskOutput) -> &str {
+        match &output.result {
+            task::TaskResult::Value(Some(value)) => value,
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    fn source(dir: &TempDir) -> PathBuf {
+        let path = dir.path().join("source.txt");
+        std::fs::write(&path, "hello").unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn checksum_is_sha256_of_file() {
+        let dir = TempDir::new().unwrap();
+
+        let output = Checksum
+            .execute(&task(&[&source(&dir)]), dir.path())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            value(&output),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[tokio::test]
+    async fn compressed_file_is_artifact() {
+        let dir = TempDir::new().unwrap();
+        let src = source(&dir);
+
+        let output = Compress.execute(&task(&[&src]), dir.path()).await.unwrap();
+
+        let dst = dir.path().join("source.txt.gz");
+        assert_eq!(output.artifacts, [dst.as_path()]);
+        let mut content = String::new();
+        GzDecoder::new(File::open(&dst).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "hello");
+    }
+
+    #[tokio::test]
+    async fn compress_never_overwrites_destination() {
+        let dir = TempDir::new().unwrap();
+        let src = source(&dir);
+        let dst = dir.path().join("existing.gz");
+        std::fs::write(&dst, "keep").unwrap();
+
+        assert!(Compress
+            .execute(&task(&[&src, &dst]), dir.path())
+            .await
+            .is_err());
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "keep");
+    }
+
+    #[tokio::test]
+    async fn copy_reports_copied_bytes() {
+        let dir = TempDir::new().unwrap();
+        let dst = dir.path().join("copy.txt");
+
+        let output = Copy
+            .execute(&task(&[&source(&dir), &dst]), dir.path())
+            .await
+            .unwrap();
+
+        assert_eq!(value(&output), "5");
+        assert_eq!(output.artifacts, [dst.as_path()]);
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn missing_file_fails() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing");
+
+        assert!(Checksum
+            .execute(&task(&[&missing]), dir.path())
+            .await
+            .is_err());
+        assert!(Copy.execute(&task(&[&missing]), dir.path()).await.is_err());
+    }
+}
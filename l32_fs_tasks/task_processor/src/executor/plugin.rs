@@ -0,0 +1,145 @@
+use super::{env, stdout, Executor};
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use task::{Task, TaskOutput};
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::trace;
+
+/// External program which gets the task as JSON on stdin
+/// and replies with the output value on stdout, JSON reply is kept as structured result as well.
+#[derive(Clone)]
+pub struct Plugin {
+    program: PathBuf,
+}
+
+impl Plugin {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+}
+
+impl Executor for Plugin {
+    fn execute<'a>(
+        &'a self,
+        task: &'a Task,
+        attachments: &'a Path,
+    ) -> BoxFuture<'a, Result<TaskOutput>> {
+        Box::pin(async move {
+            trace!(
+                program = self.program.to_string_lossy().as_ref(),
+                "running plugin"
+            );
+
+            let input = task::to_json(task).context("Serializing task")?;
+
+            let mut command = Command::new(&self.program);
+            env(&mut command, task, attachments);
+
+            let mut child = command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .context("Spawning plugin")?;
+
+            // Task is written while output is read, otherwise plugin which replies
+            // before it reads the whole task blocks on the full pipe.
+            let mut stdin = child.stdin.take().context("Getting plugin stdin")?;
+            let writing = async move {
+                stdin.write_all(&input).await?;
+                // Plugin sees the end of the task once stdin is closed.
+                drop(stdin);
+                Ok::<_, std::io::Error>(())
+            };
+
+            let (written, output) = tokio::join!(writing, child.wait_with_output());
+            let output = output.context("Waiting for plugin")?;
+            written.context("Writing task to plugin")?;
+
+            let mut output = stdout(output)?;
+            output.data = output
+                .stdout
+                .as_deref()
+                .and_then(|stdout| serde_json::from_str(stdout).ok())
+                .unwrap_or_default();
+
+            Ok(output)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{os::unix::fs::PermissionsExt, time::Duration};
+    use task::test_util::task;
+    use tempfile::TempDir;
+
+    fn plugin(dir: &TempDir, script: &str) -> Plugin {
+        let path = dir.path().join("plugin.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        Plugin::new(path)
+    }
+
+    #[tokio::test]
+    async fn plugin_gets_task_on_stdin() {
+        let dir = TempDir::new().unwrap();
+        let plugin = plugin(&dir, "cat");
+
+        let output = plugin.execute(&task(7), dir.path()).await.unwrap();
+
+        // Echoed task is JSON, so it's kept as structured result.
+        let echoed = task::from_json::<Task>(output.stdout.unwrap().as_bytes()).unwrap();
+        assert_eq!(echoed.id, task(7).id);
+        assert_eq!(output.data["id"], task(7).id.to_string());
+    }
+
+    #[tokio::test]
+    async fn plain_reply_has_no_structured_result() {
+        let dir = TempDir::new().unwrap();
+        let plugin = plugin(&dir, "cat > /dev/null; echo done");
+
+        let output = plugin.execute(&task(1), dir.path()).await.unwrap();
+
+        assert!(output.is_ok());
+        assert!(output.data.is_null());
+    }
+
+    #[tokio::test]
+    async fn plugin_replying_before_reading_task_is_not_blocked() {
+        let dir = TempDir::new().unwrap();
+        let plugin = plugin(&dir, "echo early");
+        let task = Task {
+            description: "x".repeat(1 << 20),
+            ..task(1)
+        };
+
+        let res = tokio::time::timeout(Duration::from_secs(10), plugin.execute(&task, dir.path()))
+            .await
+            .expect("plugin hangs");
+
+        // Plugin may exit before the task is written, it's reported but never hangs.
+        if let Ok(output) = res {
+            assert_eq!(output.stdout.as_deref(), Some("early\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_program_fails() {
+        let dir = TempDir::new().unwrap();
+
+        let res = Plugin::new(dir.path().join("missing"))
+            .execute(&task(1), dir.path())
+            .await;
+
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use super::{arg, env, stdout, Executor};
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use std::path::Path;
+use task::{Task, TaskOutput};
+use tokio::process::Command;
+use tracing::trace;
+
+/// Runs the first argument as `sh -c` command line,
+/// rest of arguments are passed as positional parameters.
+/// Payload and folder of attachments are passed in `TASK_PAYLOAD` and `TASK_ATTACHMENTS`.
+pub struct Shell;
+
+impl Executor for Shell {
+    fn execute<'a>(
+        &'a self,
+        task: &'a Task,
+        attachments: &'a Path,
+    ) -> BoxFuture<'a, Result<TaskOutput>> {
+        Box::pin(async move {
+            let command = arg(task, 0, "command")?;
+            trace!(command, "running shell command");
+
+            let mut sh = Command::new("sh");
+            sh.arg("-c")
+                .arg(command)
+                .arg("sh")
+                .args(&task.args[1..])
+                .kill_on_drop(true);
+            env(&mut sh, task, attachments);
+
+            let output = sh.output().await.context("Running shell command")?;
+
+            stdout(output)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::ProgramFailed;
+    use serde_json::json;
+    use task::test_util;
+
+    fn task(args: &[&str]) -> Task {
+        Task {
+            kind: "shell".to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            ..test_util::task(1)
+        }
+    }
+
+    #[tokio::test]
+    async fn output_value_is_trimmed_stdout() {
+        let task = task(&["echo \"$1-$2\"; echo warning >&2", "a", "b"]);
+
+        let output = Shell.execute(&task, Path::new("")).await.unwrap();
+
+        assert!(
+            matches!(output.result, task::TaskResult::Value(Some(ref value)) if value == "a-b")
+        );
+        assert_eq!(output.exit_code, Some(0));
+        assert_eq!(output.stdout.as_deref(), Some("a-b\n"));
+        assert_eq!(output.stderr.as_deref(), Some("warning\n"));
+    }
+
+    #[tokio::test]
+    async fn payload_is_passed_in_environment() {
+        let task = Task {
+            payload: json!({ "key": "value" }),
+            ..task(&["printf %s \"$TASK_PAYLOAD\""])
+        };
+
+        let output = Shell.execute(&task, Path::new("")).await.unwrap();
+
+        assert_eq!(output.stdout.as_deref(), Some(r#"{"key":"value"}"#));
+    }
+
+    #[tokio::test]
+    async fn failed_command_keeps_what_it_printed() {
+        let task = task(&["echo partial; echo broken >&2; exit 3"]);
+
+        let why = Shell.execute(&task, Path::new("")).await.unwrap_err();
+
+        let failed = why.downcast_ref::<ProgramFailed>().unwrap();
+        assert_eq!(failed.status.code(), Some(3));
+        assert_eq!(failed.stdout, "partial\n");
+        assert_eq!(failed.stderr, "broken\n");
+    }
+
+    #[tokio::test]
+    async fn command_is_required() {
+        assert!(Shell.execute(&task(&[]), Path::new("")).await.is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{lease::Leases, schedule, Outcome, Processor};
+use futures::{Stream, StreamExt};
+use std::path::PathBuf;
+use task::Task;
+use tokio::sync::mpsc;
+use tracing::{error, trace};
+
+pub struct Options {
+    pub workers: usize,
+    pub ordered: bool,
+}
+
+/// Handles task files until they end, tasks which are taken already are completed before it returns.
+/// Files are claimed with leases if processors share the folder.
+pub async fn run(
+    processor: &Processor,
+    task_files: impl Stream<Item = PathBuf> + Unpin,
+    leases: Option<&Leases>,
+    options: Options,
+) {
+    // Failed tasks are handed back to the scheduler, which holds them until their next attempt.
+    let (retry_tx, retries) = mpsc::unbounded_channel();
+
+    let comp_tasks = schedule::schedule(
+        task_files,
+        retries,
+        processor.outcomes.clone(),
+        leases,
+        processor.poll_interval,
+    )
+    .map(|task_file| async move {
+        trace!(
+            file = task_file.to_string_lossy().as_ref(),
+            "proceeding task file"
+        );
+        let received = processor.inbox.open::<Task>(&task_file).await;
+        let outcome = match &received {
+            Ok(Some(received)) => Some(processor.handle(received).await),
+            _ => None,
+        };
+        (task_file, received, outcome)
+    });
+
+    // Ordered mode runs tasks concurrently as well,
+    // but stores their results in order of arrival.
+    let comp_tasks = if options.ordered {
+        comp_tasks.buffered(options.workers).left_stream()
+    } else {
+        comp_tasks.buffer_unordered(options.workers).right_stream()
+    };
+
+    comp_tasks
+        .for_each(|(task_file, received, outcome)| {
+            let retry_tx = &retry_tx;
+            async move {
+                let retrying = matches!(outcome, Some(Outcome::Retry(_)));
+                // Unreadable file is moved into dead-letter folder already.
+                let res = match (received, outcome) {
+                    (Ok(Some(received)), Some(outcome)) => {
+                        processor.settle(&received, outcome).await
+                    }
+                    (Ok(_), _) => Ok(()),
+                    (Err(why), _) => Err(why),
+                };
+                let done = res.is_ok() && !retrying;
+                if let Err(why) = res {
+                    error!("failed to proceed task file: {:?}", why);
+                }
+                if let Some(leases) = leases {
+                    if let Err(why) = leases.release(&task_file, done).await {
+                        error!("failed to release task file: {:?}", why);
+                    }
+                }
+                // Scheduler is gone only once the files end, the file is taken after restart then.
+                if retrying {
+                    let _ = retry_tx.send(task_file);
+                }
+            }
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{processor, shell};
+    use futures::stream;
+    use std::time::Duration;
+    use task::{CompletedTask, Message};
+    use tempfile::TempDir;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn task_in_progress_is_completed_after_files_end() {
+        let dir = TempDir::new().unwrap();
+        let processor = processor(&dir, None).await;
+        let (slow, next) = (shell(1, "sleep 0.5"), shell(2, "true"));
+        let mut files = Vec::new();
+        for task in [&slow, &next] {
+            let path = processor.tasks.join(format!("{}.json", task.id()));
+            std::fs::write(&path, task::to_json(task).unwrap()).unwrap();
+            files.push(path);
+        }
+        // Files end while the first task is in progress, like on shutdown.
+        let task_files = stream::iter(files.clone())
+            .chain(stream::pending())
+            .take_until(time::sleep(Duration::from_millis(100)))
+            .boxed();
+
+        run(
+            &processor,
+            task_files,
+            None,
+            Options {
+                workers: 1,
+                ordered: false,
+            },
+        )
+        .await;
+
+        let completed = std::fs::read_dir(dir.path().join("output"))
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                task::decode::<CompletedTask>(&path, &std::fs::read(&path).unwrap()).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].task.id, slow.id);
+        assert!(completed[0].output.is_ok());
+        assert!(!files[0].exists());
+        assert!(files[1].exists());
+    }
+}
@@ -0,0 +1,428 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::File,
+    io::{self, ErrorKind},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::{fs, sync::oneshot, task::JoinHandle, time};
+use tracing::{debug, trace, warn};
+
+/// Content of lease file.
+#[derive(Debug, Deserialize, Serialize)]
+struct Lease {
+    worker: String,
+    /// Lease of consumed task never expires.
+    until: Option<DateTime<Utc>>,
+}
+
+pub enum Claim {
+    /// Task is leased by this processor.
+    Taken,
+    /// Task is leased by another processor until the time.
+    Leased(DateTime<Utc>),
+    /// Task is consumed already.
+    Done,
+}
+
+/// Stops renewal of the lease and waits for it.
+type Renewal = (oneshot::Sender<()>, JoinHandle<()>);
+
+/// Claims task files with lease files beside them,
+/// so processors sharing the folder run each task once.
+/// Leases are renewed while tasks run, so tasks of crashed processor are claimed again once leases expire.
+pub struct Leases {
+    worker: String,
+    duration: Duration,
+    /// Renewals of leases held by this processor.
+    held: Mutex<HashMap<PathBuf, Renewal>>,
+}
+
+impl Leases {
+    pub fn new(worker: String, duration: Duration) -> Self {
+        Self {
+            worker,
+            duration,
+            held: Mutex::default(),
+        }
+    }
+
+    pub async fn claim(&self, task_file: &Path) -> Result<Claim> {
+        let path = lease_file(task_file);
+
+        loop {
+            if self.create(&path).await? {
+                break;
+            }
+
+            match self.take_over(task_file, &path).await? {
+                Some(Claim::Taken) => break,
+                Some(claim) => return Ok(claim),
+                // Lease is released meanwhile.
+                None => continue,
+            }
+        }
+
+        // Previous holder may consume the file since it was noticed.
+        if !fs::try_exists(task_file)
+            .await
+            .context("Checking task file")?
+        {
+            remove(&path).await?;
+            return Ok(Claim::Done);
+        }
+
+        let (stop, stopped) = oneshot::channel();
+        let renewal = tokio::spawn(renew(path, self.worker.clone(), self.duration, stopped));
+        self.held
+            .lock()
+            .unwrap()
+            .insert(task_file.to_path_buf(), (stop, renewal));
+
+        trace!(file = ?task_file, "task claimed");
+
+        Ok(Claim::Taken)
+    }
+
+    /// Gives up the lease, lease of done task which file stays in place
+    /// is kept to mark it as done for other processors.
+    pub async fn release(&self, task_file: &Path, done: bool) -> Result<()> {
+        let renewal = self.held.lock().unwrap().remove(task_file);
+        if let Some((stop, renewal)) = renewal {
+            // Renewal which is in progress would overwrite the lease written below.
+            let _ = stop.send(());
+            let _ = renewal.await;
+        }
+
+        let path = lease_file(task_file);
+        if done
+            && fs::try_exists(task_file)
+                .await
+                .context("Checking task file")?
+        {
+            let lease = Lease {
+                worker: self.worker.clone(),
+                until: None,
+            };
+            replace(&path, &lease).await
+        } else {
+            remove(&path).await
+        }
+    }
+
+    /// Creates lease file unless it exists.
+    async fn create(&self, path: &Path) -> Result<bool> {
+        let lease = Lease {
+            worker: self.worker.clone(),
+            until: Some(Utc::now() + self.duration),
+        };
+        let tmp_path = aside(path, &self.worker, "tmp");
+        write(&tmp_path, &lease).await?;
+
+        // Link fails if the file exists, so only one processor gets the lease.
+        let res = fs::hard_link(&tmp_path, path).await;
+        let _ = fs::remove_file(&tmp_path).await;
+
+        match res {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e).context("Creating lease file"),
+        }
+    }
+
+    /// Replaces expired or unreadable lease of the task, unless the lease is released meanwhile.
+    /// Processors take over the lease one by one under the lock of the task file,
+    /// the lock is gone along with the processor which crashes holding it.
+    async fn take_over(&self, task_file: &Path, path: &Path) -> Result<Option<Claim>> {
+        let file = task_file.to_path_buf();
+        let locked = tokio::task::spawn_blocking(move || lock(&file))
+            .await
+            .context("Waiting for task file lock")?;
+        let _lock = match locked {
+            Ok(lock) => lock,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Some(Claim::Done)),
+            Err(e) => return Err(e).context("Locking task file"),
+        };
+
+        match read(path).await {
+            Ok(None) => return Ok(None),
+            Ok(Some(Lease { until: None, .. })) => return Ok(Some(Claim::Done)),
+            Ok(Some(Lease {
+                worker,
+                until: Some(until),
+            })) => {
+                if until > Utc::now() {
+                    trace!(file = ?task_file, worker, %until, "task is leased");
+                    return Ok(Some(Claim::Leased(until)));
+                }
+                debug!(file = ?task_file, worker, %until, "lease expired");
+            }
+            Err(why) => warn!(file = ?path, "taking over unreadable lease: {:?}", why),
+        }
+
+        let lease = Lease {
+            worker: self.worker.clone(),
+            until: Some(Utc::now() + self.duration),
+        };
+        replace(path, &lease).await?;
+
+        Ok(Some(Claim::Taken))
+    }
+}
+
+/// Exclusive lock of the file which is held until the file is closed.
+fn lock(path: &Path) -> io::Result<File> {
+    let file = File::open(path)?;
+    // SAFETY: the descriptor is owned by the file which outlives the call.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+        Ok(file)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Extends the lease until the task is released.
+async fn renew(path: PathBuf, worker: String, duration: Duration, mut stop: oneshot::Receiver<()>) {
+    let mut interval = time::interval(duration / 3);
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = &mut stop => return,
+        }
+
+        let lease = Lease {
+            worker: worker.clone(),
+            until: Some(Utc::now() + duration),
+        };
+        match replace(&path, &lease).await {
+            Ok(()) => trace!(file = ?path, "lease renewed"),
+            Err(why) => warn!(file = ?path, "failed to renew lease: {:?}", why),
+        }
+    }
+}
+
+fn lease_file(task_file: &Path) -> PathBuf {
+    let mut path = OsString::from(task_file);
+    path.push(".lease");
+    PathBuf::from(path)
+}
+
+/// Path beside lease file which is private to the processor.
+fn aside(path: &Path, worker: &str, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(format!(".{}.{}", worker, suffix));
+    PathBuf::from(path)
+}
+
+async fn read(path: &Path) -> Result<Option<Lease>> {
+    match fs::read(path).await {
+        Ok(content) => serde_json::from_slice(&content)
+            .map(Some)
+            .context("Getting lease from file"),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context("Reading lease file"),
+    }
+}
+
+async fn write(path: &Path, lease: &Lease) -> Result<()> {
+    let content = serde_json::to_vec(lease).context("Serializing lease")?;
+    fs::write(path, content).await.context("Writing lease file")
+}
+
+/// Writes the lease aside and moves it into place, so readers never see partially written one.
+async fn replace(path: &Path, lease: &Lease) -> Result<()> {
+    let tmp_path = aside(path, &lease.worker, "tmp");
+    write(&tmp_path, lease).await?;
+    fs::rename(&tmp_path, path)
+        .await
+        .context("Moving lease file into place")
+}
+
+async fn remove(path: &Path) -> Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e).context("Removing lease file"),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use tempfile::TempDir;
+
+    const DURATION: Duration = Duration::from_secs(60);
+
+    fn task_file(dir: &TempDir) -> PathBuf {
+        let path = dir.path().join("task.json");
+        std::fs::write(&path, "{}").unwrap();
+        path
+    }
+
+    fn leases(worker: &str) -> Leases {
+        Leases::new(worker.to_string(), DURATION)
+    }
+
+    async fn write_lease(task_file: &Path, worker: &str, until: Option<DateTime<Utc>>) {
+        let lease = Lease {
+            worker: worker.to_string(),
+            until,
+        };
+        write(&lease_file(task_file), &lease).await.unwrap();
+    }
+
+    /// Claims of processors running in parallel.
+    async fn claim_concurrently(task_file: &Path) -> Vec<Result<Claim>> {
+        let claims = (0..16).map(|i| {
+            let task_file = task_file.to_path_buf();
+            tokio::spawn(async move { leases(&i.to_string()).claim(&task_file).await })
+        });
+        future::try_join_all(claims).await.unwrap()
+    }
+
+    async fn holder(task_file: &Path) -> Option<Lease> {
+        read(&lease_file(task_file)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn claimed_task_is_leased_for_others() {
+        let dir = TempDir::new().unwrap();
+        let file = task_file(&dir);
+
+        assert!(matches!(leases("a").claim(&file).await, Ok(Claim::Taken)));
+        assert!(matches!(
+            leases("b").claim(&file).await,
+            Ok(Claim::Leased(until)) if until > Utc::now()
+        ));
+        assert_eq!(holder(&file).await.unwrap().worker, "a");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn only_one_of_concurrent_claims_is_taken() {
+        let dir = TempDir::new().unwrap();
+        let file = task_file(&dir);
+        let claims = claim_concurrently(&file).await;
+
+        let taken = claims
+            .iter()
+            .filter(|claim| matches!(claim, Ok(Claim::Taken)))
+            .count();
+        assert_eq!(taken, 1);
+        assert!(claims.iter().all(|claim| claim.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn expired_lease_is_taken_over() {
+        let dir = TempDir::new().unwrap();
+        let file = task_file(&dir);
+        write_lease(&file, "crashed", Some(Utc::now() - DURATION)).await;
+
+        assert!(matches!(leases("a").claim(&file).await, Ok(Claim::Taken)));
+        let lease = holder(&file).await.unwrap();
+        assert_eq!(lease.worker, "a");
+        assert!(lease.until.unwrap() > Utc::now());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn only_one_of_concurrent_take_overs_is_taken() {
+        let dir = TempDir::new().unwrap();
+        let file = task_file(&dir);
+        write_lease(&file, "crashed", Some(Utc::now() - DURATION)).await;
+        let claims = claim_concurrently(&file).await;
+
+        let taken = claims
+            .iter()
+            .filter(|claim| matches!(claim, Ok(Claim::Taken)))
+            .count();
+        assert_eq!(taken, 1);
+        // Nothing but the task and its lease is left.
+        let files = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(files, 2);
+    }
+
+    #[tokio::test]
+    async fn unreadable_lease_is_taken_over() {
+        let dir = TempDir::new().unwrap();
+        let file = task_file(&dir);
+        std::fs::write(lease_file(&file), "garbage").unwrap();
+
+        assert!(matches!(leases("a").claim(&file).await, Ok(Claim::Taken)));
+        assert_eq!(holder(&file).await.unwrap().worker, "a");
+    }
+
+    #[tokio::test]
+    async fn lease_is_renewed_while_task_runs() {
+        let dir = TempDir::new().unwrap();
+        let file = task_file(&dir);
+        let leases = Leases::new("a".to_string(), Duration::from_millis(300));
+        leases.claim(&file).await.unwrap();
+        let first = holder(&file).await.unwrap().until.unwrap();
+
+        time::sleep(Duration::from_millis(500)).await;
+
+        let renewed = holder(&file).await.unwrap().until.unwrap();
+        assert!(renewed > first);
+        assert!(matches!(
+            self::leases("b").claim(&file).await,
+            Ok(Claim::Leased(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn released_lease_may_be_claimed_again() {
+        let dir = TempDir::new().unwrap();
+        let file = task_file(&dir);
+        let a = Leases::new("a".to_string(), Duration::from_millis(30));
+        a.claim(&file).await.unwrap();
+
+        a.release(&file, false).await.unwrap();
+        // Renewal is stopped, so it doesn't bring the lease back.
+        time::sleep(Duration::from_millis(50)).await;
+
+        assert!(holder(&file).await.is_none());
+        assert!(matches!(leases("b").claim(&file).await, Ok(Claim::Taken)));
+    }
+
+    #[tokio::test]
+    async fn lease_of_done_task_marks_it_done_for_others() {
+        let dir = TempDir::new().unwrap();
+        let file = task_file(&dir);
+        let a = leases("a");
+        a.claim(&file).await.unwrap();
+
+        a.release(&file, true).await.unwrap();
+
+        assert!(holder(&file).await.unwrap().until.is_none());
+        assert!(matches!(leases("b").claim(&file).await, Ok(Claim::Done)));
+    }
+
+    #[tokio::test]
+    async fn lease_of_consumed_task_is_removed() {
+        let dir = TempDir::new().unwrap();
+        let file = task_file(&dir);
+        let a = leases("a");
+        a.claim(&file).await.unwrap();
+
+        std::fs::remove_file(&file).unwrap();
+        a.release(&file, true).await.unwrap();
+
+        assert!(holder(&file).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn task_consumed_before_claim_is_done() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("task.json");
+
+        assert!(matches!(leases("a").claim(&file).await, Ok(Claim::Done)));
+        assert!(holder(&file).await.is_none());
+    }
+}
@@ -1,24 +1,49 @@
-use anyhow::{Context, Error, Result};
+mod dependencies;
+mod executor;
+mod folder;
+mod lease;
+mod schedule;
+mod store;
+mod telemetry;
+
+use anyhow::{Context, Result};
 use chrono::Utc;
-use clap::Parser;
-use futures::StreamExt;
-use notifier::try_watch;
+use clap::{value_parser, Parser, Subcommand};
+use dependencies::{Outcomes, Places, Settled};
+use executor::{Builtin, Plugin, ProgramFailed, Registry};
+use futures::{future, StreamExt};
+use lease::Leases;
+use metrics::{counter, gauge, histogram};
+use notifier::{
+    cli::{BacklogOrder, OnConsumed, Watcher},
+    Bookkeeper, Notifier, ARCHIVE_DIR,
+};
 use std::{
     env,
+    net::SocketAddr,
     path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+use task::{
+    Codec, CompletedTask, Encoding, Inbox, Received, RetryPolicy, SchemaOf, Store, Task,
+    TaskOutput, TaskTransport, COMPLETED, EXTENSIONS, TASKS,
 };
-use task::{CompletedTask, Task, TaskOutput};
+use thiserror::Error;
 use tokio::{
-    fs::{self, File},
-    io::AsyncWriteExt,
+    signal::{self, unix::SignalKind},
+    time,
 };
-use tracing::{debug, error, trace};
+use tracing::{debug, error, info, trace, warn};
 use tracing_subscriber::EnvFilter;
 use uuid::Uuid;
 
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Folder of created tasks.
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Folder of created tasks and their attachments.
     #[clap(
         short,
         long,
@@ -35,6 +60,171 @@ struct Cli {
         env = "WBTECH_L32_PROCESSOR_OUTPUT"
     )]
     output: PathBuf,
+
+    /// Format of handled task files, input files of any format are read.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = Encoding::Json,
+        env = "WBTECH_L32_PROCESSOR_ENCODING"
+    )]
+    encoding: Encoding,
+
+    /// Compress handled task files with zstd.
+    #[clap(long, env = "WBTECH_L32_PROCESSOR_ZSTD")]
+    zstd: bool,
+
+    /// SQLite database to claim tasks from and send completed tasks to,
+    /// instead of the input and output folders.
+    #[clap(long, env = "WBTECH_L32_PROCESSOR_STORE")]
+    store: Option<PathBuf>,
+
+    /// Id of the processor which claims task files with lease files,
+    /// so several processors can share the input folder.
+    #[clap(long, env = "WBTECH_L32_PROCESSOR_WORKER_ID")]
+    worker_id: Option<String>,
+
+    /// Seconds a claimed task is hidden from other processors,
+    /// it is claimed again once the lease expires.
+    #[clap(
+        long,
+        value_parser = value_parser!(u64).range(1..),
+        default_value_t = 600,
+        env = "WBTECH_L32_PROCESSOR_LEASE"
+    )]
+    lease: u64,
+
+    /// Order of files which already exist in the input folder at start.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = BacklogOrder::Mtime,
+        env = "WBTECH_L32_PROCESSOR_BACKLOG"
+    )]
+    backlog: BacklogOrder,
+
+    /// What to do with input files once they are handled.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = OnConsumed::Journal,
+        env = "WBTECH_L32_PROCESSOR_ON_CONSUMED"
+    )]
+    on_consumed: OnConsumed,
+
+    /// Watch subfolders of the input folder as well.
+    #[clap(long, env = "WBTECH_L32_PROCESSOR_RECURSIVE")]
+    recursive: bool,
+
+    /// How changes of the input folder are noticed.
+    #[clap(
+        long,
+        value_enum,
+        default_value_t = Watcher::Inotify,
+        env = "WBTECH_L32_PROCESSOR_WATCHER"
+    )]
+    watcher: Watcher,
+
+    /// Interval of polling the input folder or the store, and outcomes of dependencies, in milliseconds.
+    #[clap(
+        long,
+        value_parser = value_parser!(u64).range(1..),
+        default_value_t = 1000,
+        env = "WBTECH_L32_PROCESSOR_POLL_INTERVAL"
+    )]
+    poll_interval: u64,
+
+    /// Report a file once while its events keep coming within the period in milliseconds, off by default.
+    #[clap(
+        long,
+        value_parser = value_parser!(u64).range(1..),
+        env = "WBTECH_L32_PROCESSOR_DEBOUNCE"
+    )]
+    debounce: Option<u64>,
+
+    /// Built-in executors to enable besides `noop`, they run commands or touch files of the host.
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        env = "WBTECH_L32_PROCESSOR_EXECUTORS"
+    )]
+    executors: Vec<Builtin>,
+
+    /// Subprocess executor given as `kind=program`,
+    /// the program gets task JSON on stdin and replies on stdout.
+    #[clap(
+        long,
+        value_parser = parse_plugin,
+        value_delimiter = ',',
+        env = "WBTECH_L32_PROCESSOR_PLUGINS"
+    )]
+    plugin: Vec<(String, PathBuf)>,
+
+    /// Folder to store tasks which used up their attempts.
+    #[clap(
+        long,
+        default_value = "dead-letter",
+        env = "WBTECH_L32_PROCESSOR_DEAD_LETTER"
+    )]
+    dead_letter: PathBuf,
+
+    /// Journal file of logger, used to learn outcomes of dependencies which comp tasks are consumed.
+    #[clap(long, env = "WBTECH_L32_PROCESSOR_JOURNAL")]
+    journal: Option<PathBuf>,
+
+    /// Attempts per task unless the task sets its own retry policy.
+    #[clap(
+        long,
+        value_parser = value_parser!(u32).range(1..),
+        default_value_t = 1,
+        env = "WBTECH_L32_PROCESSOR_MAX_ATTEMPTS"
+    )]
+    max_attempts: u32,
+
+    /// Delay in milliseconds after the first failed attempt, doubled for each next one.
+    #[clap(long, default_value_t = 1000, env = "WBTECH_L32_PROCESSOR_BACKOFF")]
+    backoff: u64,
+
+    /// Upper limit of delay between attempts in milliseconds.
+    #[clap(long, env = "WBTECH_L32_PROCESSOR_MAX_BACKOFF")]
+    max_backoff: Option<u64>,
+
+    /// Number of tasks executed concurrently.
+    #[clap(
+        short,
+        long,
+        value_parser = value_parser!(u16).range(1..),
+        default_value_t = 1,
+        env = "WBTECH_L32_PROCESSOR_WORKERS"
+    )]
+    workers: u16,
+
+    /// Store results in order of task arrival.
+    #[clap(long, env = "WBTECH_L32_PROCESSOR_ORDERED")]
+    ordered: bool,
+
+    /// Address to serve Prometheus metrics on, e.g. `0.0.0.0:9100`.
+    #[clap(long, env = "WBTECH_L32_PROCESSOR_METRICS_ADDR")]
+    metrics_addr: Option<SocketAddr>,
+}
+
+fn parse_plugin(s: &str) -> Result<(String, PathBuf), String> {
+    match s.split_once('=') {
+        Some((kind, program)) if !kind.is_empty() && !program.is_empty() => {
+            Ok((kind.to_string(), PathBuf::from(program)))
+        }
+        _ => Err(format!("expected `kind=program`, got `{}`", s)),
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print JSON Schema of the current version, to validate files before dropping them in the folder.
+    Schema {
+        #[clap(value_enum, default_value_t = SchemaOf::Task)]
+        of: SchemaOf,
+    },
 }
 
 #[tokio::main]
@@ -62,93 +252,493 @@ fn setup_tracing() {
 }
 
 async fn run() -> Result<()> {
-    let Cli { input, output } = Cli::try_parse().context("Parsing args")?;
+    let Cli {
+        command,
+        input,
+        output,
+        encoding,
+        zstd,
+        store,
+        worker_id,
+        lease,
+        backlog,
+        on_consumed,
+        recursive,
+        watcher,
+        poll_interval,
+        debounce,
+        executors,
+        plugin,
+        dead_letter,
+        journal,
+        max_attempts,
+        backoff,
+        max_backoff,
+        workers,
+        ordered,
+        metrics_addr,
+    } = Cli::try_parse().context("Parsing args")?;
+
+    if let Some(Command::Schema { of }) = command {
+        let json = serde_json::to_string_pretty(&of.json_schema()).context("Serializing schema")?;
+        println!("{}", json);
+        return Ok(());
+    }
 
-    let mut notifier = try_watch(&input).await.context("Creating notifier")?;
+    if let Some(addr) = metrics_addr {
+        telemetry::install(addr).context("Serving metrics")?;
+        info!(%addr, "serving metrics");
+    }
 
-    while let Some(try_path) = notifier.next().await {
-        let task_file = try_path.context("Getting path of task file")?;
+    let store = match store {
+        Some(path) => Some(Store::open(path).await.context("Opening store")?),
+        None => None,
+    };
+    let outcomes = Outcomes::load(Places {
+        tasks: input.clone(),
+        output: output.clone(),
+        journal,
+        dead_letter: dead_letter.clone(),
+        store: store.clone(),
+    })
+    .await
+    .context("Loading outcomes of tasks")?;
 
-        trace!(
-            file = task_file.to_string_lossy().as_ref(),
-            "proceeding task file"
-        );
+    let processor = Processor {
+        inbox: Inbox::new(
+            store.as_ref().map(|store| store.queue(TASKS)),
+            Bookkeeper::new(&input, on_consumed.into()),
+            dead_letter,
+        ),
+        output: match &store {
+            Some(store) => TaskTransport::Sqlite(store.queue(COMPLETED)),
+            None => TaskTransport::Dir(output, Codec { encoding, zstd }),
+        },
+        tasks: input.clone(),
+        outcomes: outcomes.clone(),
+        registry: {
+            let mut registry = Registry::with_builtins(&executors);
+            for (kind, program) in plugin {
+                registry.register(kind, Plugin::new(program));
+            }
+            registry
+        },
+        retry: RetryPolicy {
+            max_attempts,
+            backoff_ms: backoff,
+            max_backoff_ms: max_backoff,
+        },
+        poll_interval: Duration::from_millis(poll_interval),
+    };
 
-        if let Err(why) = proceed(task_file, &output).await {
-            error!("failed to proceed task file: {:?}", why);
-        }
+    if store.is_some() {
+        info!(workers, ordered, "start claiming tasks");
+
+        store::run(
+            &processor,
+            store::Options {
+                worker: worker_id.unwrap_or_else(|| format!("processor-{}", std::process::id())),
+                lease: Duration::from_secs(lease),
+                poll_interval: Duration::from_millis(poll_interval),
+                workers: workers.into(),
+                ordered,
+            },
+            shutdown_signal(),
+        )
+        .await;
+
+        info!("stopped processing tasks");
+
+        return Ok(());
+    }
+
+    // Temporary files of writers are hidden.
+    let mut builder = Notifier::builder(&input)
+        .backlog(backlog.backlog(created_at))
+        .backend(watcher.backend(Duration::from_millis(poll_interval)))
+        .recursive(recursive)
+        .extensions(EXTENSIONS)
+        .ignore(".*")
+        .ignore(ARCHIVE_DIR);
+    if let Some(debounce) = debounce {
+        builder = builder.debounce(Duration::from_millis(debounce));
     }
+    let mut notifier = builder.watch().await.context("Creating notifier")?;
 
-    Ok(())
+    info!(workers, ordered, "start processing tasks");
+
+    let mut fatal = None;
+
+    let task_files = notifier
+        .by_ref()
+        .take_until(shutdown_signal())
+        .filter(|try_path| future::ready(!is_skipped(try_path)))
+        .scan(&mut fatal, |fatal, try_path| {
+            let next = try_path
+                .context("Getting path of task file")
+                .map_err(|why| **fatal = Some(why))
+                .ok();
+            future::ready(next)
+        })
+        .boxed();
+
+    let leases = worker_id.map(|worker| Leases::new(worker, Duration::from_secs(lease)));
+
+    folder::run(
+        &processor,
+        task_files,
+        leases.as_ref(),
+        folder::Options {
+            workers: workers.into(),
+            ordered,
+        },
+    )
+    .await;
+
+    if let Err(why) = notifier.close() {
+        error!("failed to close notifier: {:?}", why);
+    }
+
+    info!("stopped processing tasks");
+
+    fatal.map_or(Ok(()), Err)
 }
-async fn proceed(from: impl AsRef<Path>, into: impl AsRef<Path>) -> Result<()> {
-    let task = read(from).await?;
-    let comp_task = execute(task).await?;
-    save(comp_task, into).await
+
+/// Creation time of the task in the file, for backlog ordered by it.
+fn created_at(path: &Path) -> Option<SystemTime> {
+    let content = std::fs::read(path).ok()?;
+    let task = task::decode::<Task>(path, &content).ok()?;
+    Some(task.created_at.into())
 }
 
-async fn read(path: impl AsRef<Path>) -> Result<Task> {
-    trace!(
-        file = path.as_ref().to_string_lossy().as_ref(),
-        "reading task file"
-    );
+/// Lost events and failed listings do not stop processing,
+/// files are left in the folder for backlog of the next run.
+fn is_skipped(try_path: &Result<PathBuf, notifier::Error>) -> bool {
+    match try_path {
+        Err(notifier::Error::Overflow) => {
+            warn!("notifier lost events, some task files are left until restart");
+            true
+        }
+        Err(notifier::Error::Scan(why)) => {
+            warn!(
+                "notifier failed to list folder, some task files are left until restart: {:?}",
+                why
+            );
+            true
+        }
+        _ => false,
+    }
+}
 
-    let content = fs::read(path)
-        .await
-        .inspect(|bytes| trace!("read {} bytes", bytes.len()))
-        .context("Reading task file")?;
+async fn shutdown_signal() {
+    let terminate = async {
+        match signal::unix::signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(why) => {
+                error!("failed to listen for SIGTERM: {:?}", why);
+                future::pending::<()>().await;
+            }
+        }
+    };
 
-    let task = serde_json::from_slice(&content)
-        .inspect(|task| debug!(?task, "task extracted"))
-        .context("Getting task from file")?;
+    tokio::select! {
+        res = signal::ctrl_c() => {
+            if let Err(why) = res {
+                error!("failed to listen for SIGINT: {:?}", why);
+            }
+        }
+        _ = terminate => {}
+    }
 
-    Ok(task)
+    info!("shutdown signal received, waiting for tasks in progress");
 }
 
-async fn execute(task: Task) -> Result<CompletedTask, Error> {
-    debug!(?task, "executing task");
+/// What is done with the received task once it is handled.
+enum Outcome {
+    /// Task is completed and forwarded to logger.
+    Completed(Box<CompletedTask>),
+    /// Task failed and is taken again after the delay.
+    Retry(Duration),
+    /// Task waits for its dependencies, the attempt is not counted.
+    Wait(Duration),
+    /// Task used up its attempts, dead letter is its only record.
+    DeadLetter(anyhow::Error),
+}
 
-    return executing(task)
-        .await
-        .inspect(|comp_task| debug!(?comp_task, "task executed"))
-        .context("Executing task");
+struct Processor {
+    /// Where tasks are taken from, both files and claimed tasks are settled through it.
+    inbox: Inbox,
+    output: TaskTransport,
+    /// Folder of created tasks, where attachments of tasks are kept.
+    tasks: PathBuf,
+    outcomes: Outcomes,
+    registry: Registry,
+    /// Used for tasks without own retry policy.
+    retry: RetryPolicy,
+    /// Delay of tasks which wait for their dependencies.
+    poll_interval: Duration,
+}
+
+impl Processor {
+    /// Runs one attempt of the received task and decides what is done with it.
+    async fn handle(&self, received: &Received<Task>) -> Outcome {
+        let (task, attempt) = (received.message(), received.attempt());
+
+        // Claimed tasks are ordered by priority and time only, so dependencies are checked here.
+        // Dependencies may be completed by other processors meanwhile.
+        if !self.outcomes.is_settled(&task.depends_on) {
+            if let Err(why) = self.outcomes.refresh(&task.depends_on).await {
+                error!("failed to refresh outcomes of dependencies: {:?}", why);
+            }
+        }
+        if !self.outcomes.is_settled(&task.depends_on) {
+            debug!(id = %task.id, "task waits for its dependencies");
+            return Outcome::Wait(self.poll_interval);
+        }
+
+        trace!(id = %task.id, attempt, "proceeding task");
+        let started = Instant::now();
+        gauge!(telemetry::TASKS_IN_PROGRESS).increment(1);
+        let res = self.attempt(task).await;
+        gauge!(telemetry::TASKS_IN_PROGRESS).decrement(1);
+        histogram!(telemetry::PROCESSING_SECONDS).record(started.elapsed());
+
+        let output = match res {
+            Ok(output) => output,
+            Err(why) => {
+                let policy = task.retry.unwrap_or(self.retry);
+                if let Some(error) = final_error(&why) {
+                    warn!(attempt, "task failed, retry won't help: {}", error);
+                    failure(error, &why)
+                } else if attempt >= policy.max_attempts {
+                    warn!(attempt, "task failed, no attempts left: {:?}", why);
+                    counter!(telemetry::TASKS_PROCESSED).increment(1);
+                    counter!(telemetry::TASKS_FAILED).increment(1);
+                    return Outcome::DeadLetter(why);
+                } else {
+                    // Task is held meanwhile, so the worker is free.
+                    let delay = policy.backoff(attempt);
+                    warn!(attempt, ?delay, "task failed, retrying: {:?}", why);
+                    counter!(telemetry::TASK_RETRIES).increment(1);
+                    return Outcome::Retry(delay);
+                }
+            }
+        };
+
+        counter!(telemetry::TASKS_PROCESSED).increment(1);
+        if !output.is_ok() {
+            counter!(telemetry::TASKS_FAILED).increment(1);
+        }
 
-    async fn executing(task: Task) -> Result<CompletedTask> {
-        Ok(CompletedTask {
+        Outcome::Completed(Box::new(CompletedTask {
             id: Uuid::new_v4(),
-            task,
-            output: TaskOutput::Value(None),
+            task: task.clone(),
+            output,
             completed_at: Utc::now(),
-        })
+        }))
+    }
+
+    /// Settles the received task by its outcome.
+    async fn settle(&self, received: &Received<Task>, outcome: Outcome) -> Result<()> {
+        let id = received.message().id;
+        match outcome {
+            Outcome::Completed(comp_task) => {
+                let ok = comp_task.output.is_ok();
+                self.inbox
+                    .forward(received, &self.output, comp_task.as_ref())
+                    .await?;
+                self.outcomes.insert(id, ok);
+            }
+            Outcome::Retry(delay) => self.inbox.retry(received, delay).await?,
+            Outcome::Wait(delay) => self.inbox.postpone(received, delay).await?,
+            // Dependents of the task fail.
+            Outcome::DeadLetter(why) => {
+                self.inbox.dead_letter(received, &why).await?;
+                self.outcomes.insert(id, false);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the task once unless its dependency failed.
+    async fn attempt(&self, task: &Task) -> Result<TaskOutput> {
+        match self.outcomes.failed(&task.depends_on) {
+            Some((id, Settled::Unknown)) => Err(DependencyUnknown(id).into()),
+            Some((id, _)) => Err(DependencyFailed(id).into()),
+            None => execute(task, &task.attachments_dir(&self.tasks), &self.registry).await,
+        }
+    }
+}
+
+/// Output of the failed task keeps what the failed program printed.
+fn failure(error: String, why: &anyhow::Error) -> TaskOutput {
+    let mut output = TaskOutput::error(error);
+    if let Some(failed) = why.downcast_ref::<ProgramFailed>() {
+        output.exit_code = failed.status.code();
+        output.stdout = Some(failed.stdout.clone()).filter(|stdout| !stdout.is_empty());
+        output.stderr = Some(failed.stderr.clone()).filter(|stderr| !stderr.is_empty());
     }
+    output
 }
 
-async fn save(comp_task: CompletedTask, path: impl AsRef<Path>) -> Result<()> {
-    debug!(?comp_task, "saving comp task");
+/// Error of the task which retries can't fix.
+fn final_error(why: &anyhow::Error) -> Option<String> {
+    if why.is::<DeadlineExceeded>() {
+        return Some(DeadlineExceeded.to_string());
+    }
+    if let Some(failed) = why.downcast_ref::<DependencyFailed>() {
+        return Some(failed.to_string());
+    }
+    why.downcast_ref::<DependencyUnknown>()
+        .map(DependencyUnknown::to_string)
+}
 
-    let mut file = {
-        let filename = comp_task.id.to_string();
-        let mut path = path.as_ref().join(&filename);
-        path.set_extension("json");
-        File::create_new(&path)
-            .await
-            .inspect(|_| {
-                trace!(
-                    file = path.to_string_lossy().as_ref(),
-                    "comp task file created"
-                )
-            })
-            .context("Creating comp task file")?
+async fn execute(task: &Task, attachments: &Path, registry: &Registry) -> Result<TaskOutput> {
+    debug!(?task, "executing task");
+
+    let executor = registry
+        .get(&task.kind)
+        .with_context(|| format!("Unknown task kind: {}", task.kind))?;
+
+    let executing = executor.execute(task, attachments);
+
+    // Task is aborted once it reaches complete_until.
+    let res = match task.complete_until {
+        Some(deadline) => {
+            let left = (deadline - Utc::now())
+                .to_std()
+                .map_err(|_| DeadlineExceeded)?;
+            time::timeout(left, executing)
+                .await
+                .map_err(|_| DeadlineExceeded)?
+        }
+        None => executing.await,
     };
 
-    let content = serde_json::to_vec(&comp_task)
-        .inspect(|bytes| trace!(bytes = bytes.len(), "comp task serialized"))
-        .context("Serialazing comp task")?;
+    res.inspect(|output| debug!(?output, "task executed"))
+        .context("Executing task")
+}
+
+#[derive(Debug, Error)]
+#[error("deadline exceeded")]
+struct DeadlineExceeded;
+
+#[derive(Debug, Error)]
+#[error("dependency {0} failed")]
+struct DependencyFailed(Uuid);
+
+#[derive(Debug, Error)]
+#[error("dependency {0} is unknown, it was never created or its outcome is lost")]
+struct DependencyUnknown(Uuid);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notifier::Consume;
+    use task::{test_util, TaskResult};
+    use tempfile::TempDir;
 
-    file.write_all(&content)
+    /// Processor of input folder of the dir, shell tasks are retried once.
+    pub(crate) async fn processor(dir: &TempDir, store: Option<Store>) -> Processor {
+        let (input, output, dead_letter) = (
+            dir.path().join("input"),
+            dir.path().join("output"),
+            dir.path().join("dead-letter"),
+        );
+        for folder in [&input, &output, &dead_letter] {
+            tokio::fs::create_dir(folder).await.unwrap();
+        }
+        let outcomes = Outcomes::load(Places {
+            tasks: input.clone(),
+            output: output.clone(),
+            journal: None,
+            dead_letter: dead_letter.clone(),
+            store: store.clone(),
+        })
         .await
-        .inspect(|_| trace!("written {} bytes", content.len()))
-        .context("Saving comp task into file")?;
+        .unwrap();
+
+        Processor {
+            inbox: Inbox::new(
+                store.as_ref().map(|store| store.queue(TASKS)),
+                Bookkeeper::new(&input, Consume::Delete),
+                dead_letter,
+            ),
+            output: match &store {
+                Some(store) => TaskTransport::Sqlite(store.queue(COMPLETED)),
+                None => TaskTransport::Dir(output, Codec::default()),
+            },
+            tasks: input,
+            outcomes,
+            registry: Registry::with_builtins(&[Builtin::Shell]),
+            retry: RetryPolicy {
+                max_attempts: 2,
+                backoff_ms: 10,
+                max_backoff_ms: None,
+            },
+            poll_interval: Duration::from_millis(20),
+        }
+    }
+
+    /// Shell task of the command line.
+    pub(crate) fn shell(id: u128, command: &str) -> Task {
+        Task {
+            kind: "shell".to_string(),
+            args: vec![command.to_string()],
+            ..test_util::task(id)
+        }
+    }
+
+    fn due_in(task: Task, millis: i64) -> Task {
+        Task {
+            complete_until: Some(Utc::now() + chrono::TimeDelta::milliseconds(millis)),
+            ..task
+        }
+    }
+
+    #[tokio::test]
+    async fn task_past_deadline_is_not_executed() {
+        let task = due_in(shell(1, "true"), -1000);
+        let registry = Registry::with_builtins(&[Builtin::Shell]);
+
+        let why = execute(&task, Path::new(""), &registry).await.unwrap_err();
+
+        assert!(why.is::<DeadlineExceeded>());
+    }
+
+    #[tokio::test]
+    async fn slow_task_is_aborted_at_deadline() {
+        let task = due_in(shell(1, "sleep 5"), 200);
+        let registry = Registry::with_builtins(&[Builtin::Shell]);
+
+        let started = Instant::now();
+        let why = execute(&task, Path::new(""), &registry).await.unwrap_err();
+
+        assert!(why.is::<DeadlineExceeded>());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn exceeded_deadline_completes_task_without_retries() {
+        let dir = TempDir::new().unwrap();
+        let processor = processor(&dir, None).await;
+        let task = due_in(shell(1, "sleep 5"), 200);
+        let path = processor.tasks.join(format!("{}.json", task.id));
+        tokio::fs::write(&path, task::to_json(&task).unwrap())
+            .await
+            .unwrap();
+        let received = processor.inbox.open::<Task>(&path).await.unwrap().unwrap();
+
+        let outcome = processor.handle(&received).await;
+
+        let Outcome::Completed(comp_task) = outcome else {
+            panic!("task is not completed");
+        };
+        assert_eq!(comp_task.task.id, task.id);
+        assert!(
+            matches!(comp_task.output.result, TaskResult::Error(ref error) if error == "deadline exceeded")
+        );
+    }
 }
@@ -0,0 +1,503 @@
+use crate::{
+    dependencies::{Completed, Outcomes},
+    lease::{Claim, Leases},
+    telemetry,
+};
+use chrono::{DateTime, Utc};
+use futures::{stream, FutureExt, Stream, StreamExt};
+use metrics::gauge;
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use task::{Inbox, Task};
+use tokio::{
+    fs,
+    sync::{mpsc, watch},
+    time::{self, Interval, MissedTickBehavior},
+};
+use tracing::{debug, error, trace};
+use uuid::Uuid;
+
+/// Orders task files by priority, holds ones with `run_at` in the future
+/// and ones which dependencies are not completed yet.
+/// Outcomes of awaited dependencies are polled, since other processors may complete them.
+/// Files are claimed once they are taken if processors share the folder.
+/// Held files stay in the folder, so they are scheduled again after restart.
+/// Failed tasks come back through retries and are held until their next attempt.
+/// The stream ends once the files end, tasks which are not taken yet are left in the folder.
+pub fn schedule<'a>(
+    files: impl Stream<Item = PathBuf> + Unpin + 'a,
+    retries: mpsc::UnboundedReceiver<PathBuf>,
+    outcomes: Outcomes,
+    leases: Option<&'a Leases>,
+    poll_interval: Duration,
+) -> impl Stream<Item = PathBuf> + 'a {
+    let mut polling = time::interval(poll_interval);
+    polling.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let queue = Queue {
+        files,
+        retries,
+        leases,
+        ready: BinaryHeap::new(),
+        held: BinaryHeap::new(),
+        waiting: Vec::new(),
+        changes: outcomes.subscribe(),
+        outcomes,
+        polling,
+        seq: 0,
+    };
+
+    stream::unfold(queue, |mut queue| async move {
+        let file = queue.next().await?;
+        Some((file, queue))
+    })
+}
+
+/// Higher priority goes first, then earlier arrival.
+type Ready = (i32, Reverse<u64>, PathBuf);
+
+/// Earlier time goes first.
+type Held = Reverse<(DateTime<Utc>, u64, i32, PathBuf)>;
+
+/// Task which waits for its dependencies.
+struct Waiting {
+    seq: u64,
+    priority: i32,
+    run_at: Option<DateTime<Utc>>,
+    depends_on: Vec<Uuid>,
+    file: PathBuf,
+}
+
+struct Queue<'a, S> {
+    files: S,
+    retries: mpsc::UnboundedReceiver<PathBuf>,
+    leases: Option<&'a Leases>,
+    ready: BinaryHeap<Ready>,
+    held: BinaryHeap<Held>,
+    waiting: Vec<Waiting>,
+    outcomes: Outcomes,
+    /// Wakes up waiting tasks once other tasks complete.
+    changes: watch::Receiver<Completed>,
+    /// Ticks of polling outcomes of dependencies.
+    polling: Interval,
+    /// Order of arrival.
+    seq: u64,
+}
+
+impl<S: Stream<Item = PathBuf> + Unpin> Queue<'_, S> {
+    async fn next(&mut self) -> Option<PathBuf> {
+        loop {
+            // Everything which has arrived is taken, so priorities are compared among all of them.
+            while let Some(next) = self.files.next().now_or_never() {
+                self.push(next?).await;
+            }
+            while let Ok(file) = self.retries.try_recv() {
+                self.push(file).await;
+            }
+
+            self.unblock();
+            self.release();
+
+            if let Some((priority, Reverse(seq), file)) = self.ready.pop() {
+                if !self.claim(seq, priority, &file).await {
+                    continue;
+                }
+                trace!(?file, priority, "task file taken");
+                return Some(file);
+            }
+
+            let due = self.held.peek().map(|Reverse((run_at, ..))| *run_at);
+            let wait = due
+                .and_then(|due| (due - Utc::now()).to_std().ok())
+                .unwrap_or_default();
+
+            tokio::select! {
+                next = self.files.next() => self.push(next?).await,
+                Some(file) = self.retries.recv() => self.push(file).await,
+                _ = time::sleep(wait), if due.is_some() => {}
+                Ok(()) = self.changes.changed(), if !self.waiting.is_empty() => {}
+                _ = self.polling.tick(), if !self.waiting.is_empty() => self.refresh().await,
+            }
+        }
+    }
+
+    /// New outcomes wake up waiting tasks through changes.
+    async fn refresh(&self) {
+        let mut depends_on = self
+            .waiting
+            .iter()
+            .flat_map(|task| task.depends_on.iter().copied())
+            .collect::<Vec<_>>();
+        depends_on.sort();
+        depends_on.dedup();
+
+        if let Err(why) = self.outcomes.refresh(&depends_on).await {
+            error!("failed to refresh outcomes of dependencies: {:?}", why);
+        }
+    }
+
+    async fn push(&mut self, file: PathBuf) {
+        self.seq += 1;
+
+        let (priority, run_at, depends_on) = header(&file).await;
+
+        if !self.outcomes.is_settled(&depends_on) {
+            debug!(
+                ?file,
+                priority,
+                ?depends_on,
+                "task waits for its dependencies"
+            );
+            self.waiting.push(Waiting {
+                seq: self.seq,
+                priority,
+                run_at,
+                depends_on,
+                file,
+            });
+            gauge!(telemetry::TASKS_WAITING).set(self.waiting.len() as f64);
+            return;
+        }
+
+        self.enqueue(self.seq, priority, run_at, file);
+    }
+
+    fn enqueue(&mut self, seq: u64, priority: i32, run_at: Option<DateTime<Utc>>, file: PathBuf) {
+        match run_at {
+            Some(run_at) if run_at > Utc::now() => {
+                debug!(?file, priority, %run_at, "task held until its time");
+                self.held.push(Reverse((run_at, seq, priority, file)));
+                gauge!(telemetry::TASKS_HELD).set(self.held.len() as f64);
+            }
+            _ => {
+                trace!(?file, priority, "task scheduled");
+                self.ready.push((priority, Reverse(seq), file));
+            }
+        }
+    }
+
+    /// Claims the file unless processors don't share the folder,
+    /// file which is leased by another processor is held until the lease expires.
+    async fn claim(&mut self, seq: u64, priority: i32, file: &Path) -> bool {
+        let Some(leases) = self.leases else {
+            return true;
+        };
+
+        match leases.claim(file).await {
+            Ok(Claim::Taken) => true,
+            Ok(Claim::Leased(until)) => {
+                debug!(?file, %until, "task is leased by another processor");
+                self.held
+                    .push(Reverse((until, seq, priority, file.to_path_buf())));
+                gauge!(telemetry::TASKS_HELD).set(self.held.len() as f64);
+                false
+            }
+            Ok(Claim::Done) => {
+                debug!(?file, "task is done by another processor");
+                false
+            }
+            // File is left in the folder until restart.
+            Err(why) => {
+                error!(?file, "failed to claim task file: {:?}", why);
+                false
+            }
+        }
+    }
+
+    /// Schedules tasks which dependencies are completed.
+    fn unblock(&mut self) {
+        if self.waiting.is_empty() {
+            return;
+        }
+
+        let (settled, waiting) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition::<Vec<_>, _>(|task| self.outcomes.is_settled(&task.depends_on));
+        self.waiting = waiting;
+        gauge!(telemetry::TASKS_WAITING).set(self.waiting.len() as f64);
+
+        for task in settled {
+            debug!(file = ?task.file, "dependencies of task are completed");
+            self.enqueue(task.seq, task.priority, task.run_at, task.file);
+        }
+    }
+
+    /// Moves tasks which are due into ready ones.
+    fn release(&mut self) {
+        let now = Utc::now();
+        while self
+            .held
+            .peek()
+            .is_some_and(|Reverse((run_at, ..))| *run_at <= now)
+        {
+            let Some(Reverse((_, seq, priority, file))) = self.held.pop() else {
+                break;
+            };
+            debug!(?file, priority, "held task is due");
+            self.ready.push((priority, Reverse(seq), file));
+        }
+        gauge!(telemetry::TASKS_HELD).set(self.held.len() as f64);
+    }
+}
+
+/// Priority, time to run and dependencies of the task, failed task runs at its next attempt.
+/// Unreadable task is not held by its content, processor handles its error.
+async fn header(file: &Path) -> (i32, Option<DateTime<Utc>>, Vec<Uuid>) {
+    let retry_at = Inbox::retry_at(file).await;
+
+    let task = match fs::read(file).await {
+        Ok(content) => task::decode::<Task>(file, &content)
+            .inspect_err(|why| debug!(?file, "failed to parse task file: {}", why))
+            .ok(),
+        Err(why) => {
+            debug!(?file, "failed to read task file: {}", why);
+            None
+        }
+    };
+
+    match task {
+        Some(task) => (task.priority, task.run_at.max(retry_at), task.depends_on),
+        None => (0, retry_at, Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dependencies::Places;
+    use chrono::TimeDelta;
+    use notifier::{Bookkeeper, Consume};
+    use std::time::Instant;
+    use task::test_util::task;
+    use tempfile::TempDir;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn outcomes(dir: &TempDir) -> Outcomes {
+        Outcomes::load(Places {
+            tasks: dir.path().to_path_buf(),
+            output: dir.path().join("output"),
+            journal: None,
+            dead_letter: dir.path().join("dead-letter"),
+            store: None,
+        })
+        .await
+        .unwrap()
+    }
+
+    fn save(dir: &TempDir, task: &Task) -> PathBuf {
+        let path = dir.path().join(format!("{}.json", task.id));
+        std::fs::write(&path, task::to_json(task).unwrap()).unwrap();
+        path
+    }
+
+    /// Files which arrive at once and keep the stream open.
+    fn arrived(files: Vec<PathBuf>) -> impl Stream<Item = PathBuf> + Unpin {
+        stream::iter(files).chain(stream::pending())
+    }
+
+    async fn next(scheduled: &mut (impl Stream<Item = PathBuf> + Unpin)) -> PathBuf {
+        time::timeout(TIMEOUT, scheduled.next())
+            .await
+            .expect("nothing is scheduled in time")
+            .expect("schedule is over")
+    }
+
+    #[tokio::test]
+    async fn higher_priority_goes_first_then_earlier_arrival() {
+        let dir = TempDir::new().unwrap();
+        let files = [(1, 0), (2, 5), (3, -1), (4, 5)].map(|(id, priority)| {
+            save(
+                &dir,
+                &Task {
+                    priority,
+                    ..task(id)
+                },
+            )
+        });
+        let (_retry_tx, retries) = mpsc::unbounded_channel();
+
+        let mut scheduled = Box::pin(schedule(
+            arrived(files.to_vec()),
+            retries,
+            outcomes(&dir).await,
+            None,
+            POLL_INTERVAL,
+        ));
+
+        for idx in [1, 3, 0, 2] {
+            assert_eq!(next(&mut scheduled).await, files[idx]);
+        }
+    }
+
+    #[tokio::test]
+    async fn task_is_held_until_run_at() {
+        let dir = TempDir::new().unwrap();
+        let later = save(
+            &dir,
+            &Task {
+                priority: 10,
+                run_at: Some(Utc::now() + TimeDelta::milliseconds(300)),
+                ..task(1)
+            },
+        );
+        let now = save(&dir, &task(2));
+        let (_retry_tx, retries) = mpsc::unbounded_channel();
+        let started = Instant::now();
+
+        let mut scheduled = Box::pin(schedule(
+            arrived(vec![later.clone(), now.clone()]),
+            retries,
+            outcomes(&dir).await,
+            None,
+            POLL_INTERVAL,
+        ));
+
+        assert_eq!(next(&mut scheduled).await, now);
+        assert_eq!(next(&mut scheduled).await, later);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn task_waits_for_its_dependencies() {
+        let dir = TempDir::new().unwrap();
+        let dependent = save(
+            &dir,
+            &Task {
+                priority: 10,
+                depends_on: vec![task(1).id],
+                ..task(2)
+            },
+        );
+        let dependency = save(&dir, &task(1));
+        let outcomes = outcomes(&dir).await;
+        let (_retry_tx, retries) = mpsc::unbounded_channel();
+
+        let mut scheduled = Box::pin(schedule(
+            arrived(vec![dependent.clone(), dependency.clone()]),
+            retries,
+            outcomes.clone(),
+            None,
+            POLL_INTERVAL,
+        ));
+
+        assert_eq!(next(&mut scheduled).await, dependency);
+        assert!(time::timeout(POLL_INTERVAL * 5, scheduled.next())
+            .await
+            .is_err());
+
+        outcomes.insert(task(1).id, true);
+        assert_eq!(next(&mut scheduled).await, dependent);
+    }
+
+    #[tokio::test]
+    async fn task_with_unknown_dependency_is_not_held_forever() {
+        let dir = TempDir::new().unwrap();
+        let dependent = save(
+            &dir,
+            &Task {
+                depends_on: vec![task(1).id],
+                ..task(2)
+            },
+        );
+        let outcomes = outcomes(&dir).await;
+        let (_retry_tx, retries) = mpsc::unbounded_channel();
+
+        let mut scheduled = Box::pin(schedule(
+            arrived(vec![dependent.clone()]),
+            retries,
+            outcomes.clone(),
+            None,
+            POLL_INTERVAL,
+        ));
+
+        assert_eq!(next(&mut scheduled).await, dependent);
+        assert!(outcomes.failed(&[task(1).id]).is_some());
+    }
+
+    #[tokio::test]
+    async fn retried_task_is_held_until_its_next_attempt() {
+        let dir = TempDir::new().unwrap();
+        let failed = save(&dir, &task(1));
+        let other = save(&dir, &task(2));
+        let inbox = Inbox::new(
+            None,
+            Bookkeeper::new(dir.path(), Consume::Keep),
+            dir.path().join("dead-letter"),
+        );
+        let (retry_tx, retries) = mpsc::unbounded_channel();
+
+        let mut scheduled = Box::pin(schedule(
+            arrived(vec![failed.clone()]),
+            retries,
+            outcomes(&dir).await,
+            None,
+            POLL_INTERVAL,
+        ));
+        assert_eq!(next(&mut scheduled).await, failed);
+
+        let received = inbox.open::<Task>(&failed).await.unwrap().unwrap();
+        inbox
+            .retry(&received, Duration::from_millis(300))
+            .await
+            .unwrap();
+        let started = Instant::now();
+        retry_tx.send(failed.clone()).unwrap();
+        retry_tx.send(other.clone()).unwrap();
+
+        assert_eq!(next(&mut scheduled).await, other);
+        assert_eq!(next(&mut scheduled).await, failed);
+        assert!(started.elapsed() >= Duration::from_millis(250));
+    }
+
+    #[tokio::test]
+    async fn task_done_by_another_processor_is_skipped() {
+        let dir = TempDir::new().unwrap();
+        let done = save(&dir, &task(1));
+        let other = save(&dir, &task(2));
+        let leases = Leases::new("a".to_string(), Duration::from_secs(60));
+        assert!(matches!(leases.claim(&done).await, Ok(Claim::Taken)));
+        leases.release(&done, true).await.unwrap();
+        let (_retry_tx, retries) = mpsc::unbounded_channel();
+
+        let leases = Leases::new("b".to_string(), Duration::from_secs(60));
+        let mut scheduled = Box::pin(schedule(
+            arrived(vec![done, other.clone()]),
+            retries,
+            outcomes(&dir).await,
+            Some(&leases),
+            POLL_INTERVAL,
+        ));
+
+        assert_eq!(next(&mut scheduled).await, other);
+        assert!(time::timeout(POLL_INTERVAL * 5, scheduled.next())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn schedule_ends_with_files_leaving_the_rest() {
+        let dir = TempDir::new().unwrap();
+        let file = save(&dir, &task(1));
+        let (_retry_tx, retries) = mpsc::unbounded_channel();
+
+        let scheduled = schedule(
+            stream::iter(vec![file.clone()]),
+            retries,
+            outcomes(&dir).await,
+            None,
+            POLL_INTERVAL,
+        );
+
+        let rest = time::timeout(TIMEOUT, scheduled.collect::<Vec<_>>())
+            .await
+            .unwrap();
+        assert!(rest.is_empty());
+        assert!(file.exists());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{Outcome, Processor};
+use futures::{stream, Future, StreamExt};
+use std::time::Duration;
+use task::{Received, Task};
+use tokio::time;
+use tracing::{error, warn};
+
+pub struct Options {
+    /// Name of the processor which claims tasks.
+    pub worker: String,
+    pub lease: Duration,
+    pub poll_interval: Duration,
+    pub workers: usize,
+    pub ordered: bool,
+}
+
+/// Claims tasks from the store until shutdown, tasks are claimed only when a worker is free.
+/// Retries are scheduled in the store, so they survive restarts as well.
+pub async fn run(processor: &Processor, options: Options, shutdown: impl Future<Output = ()>) {
+    let claims = stream::unfold((), |()| async {
+        let received = claim(processor, &options).await;
+        Some((received, ()))
+    })
+    .take_until(shutdown);
+
+    let outcomes = claims.map(|received| {
+        let options = &options;
+        async move {
+            let handling = processor.handle(&received);
+            let outcome = renewing(processor, &received, options.lease, handling).await;
+            (received, outcome)
+        }
+    });
+
+    let outcomes = if options.ordered {
+        outcomes.buffered(options.workers).left_stream()
+    } else {
+        outcomes.buffer_unordered(options.workers).right_stream()
+    };
+
+    outcomes
+        .for_each(|(received, outcome)| async move {
+            // Task which is not settled is claimed again once its lease expires.
+            if let Err(why) = processor.settle(&received, outcome).await {
+                error!("failed to proceed claimed task: {:?}", why);
+            }
+        })
+        .await;
+}
+
+/// Extends the lease of the claimed task while it's handled,
+/// so long tasks are not claimed by other processors meanwhile.
+async fn renewing(
+    processor: &Processor,
+    received: &Received<Task>,
+    lease: Duration,
+    handling: impl Future<Output = Outcome>,
+) -> Outcome {
+    let mut interval = time::interval(lease / 3);
+    interval.tick().await;
+    tokio::pin!(handling);
+
+    loop {
+        tokio::select! {
+            outcome = &mut handling => return outcome,
+            _ = interval.tick() => {}
+        }
+
+        if let Err(why) = processor.inbox.renew(received, lease).await {
+            warn!(id = %received.message().id, "failed to renew lease: {:?}", why);
+        }
+    }
+}
+
+/// Waits for the next due task of the queue.
+async fn claim(processor: &Processor, options: &Options) -> Received<Task> {
+    loop {
+        match processor.inbox.claim(&options.worker, options.lease).await {
+            Ok(Some(received)) => return received,
+            Ok(None) => {}
+            Err(why) => error!("failed to claim task: {:?}", why),
+        }
+        time::sleep(options.poll_interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{processor, shell};
+    use task::{CompletedTask, State, Store, COMPLETED, TASKS};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn claimed_task_is_completed_after_shutdown() {
+        let dir = TempDir::new().unwrap();
+        let store = Store::open(":memory:").await.unwrap();
+        let processor = processor(&dir, Some(store.clone())).await;
+        let (slow, next) = (shell(1, "sleep 0.5"), shell(2, "true"));
+        store.queue(TASKS).send(&slow).await.unwrap();
+        store.queue(TASKS).send(&next).await.unwrap();
+
+        run(
+            &processor,
+            Options {
+                worker: "worker".to_string(),
+                lease: Duration::from_secs(30),
+                poll_interval: Duration::from_millis(20),
+                workers: 1,
+                ordered: false,
+            },
+            time::sleep(Duration::from_millis(100)),
+        )
+        .await;
+
+        let completed = store
+            .queue(COMPLETED)
+            .messages::<CompletedTask>()
+            .await
+            .unwrap();
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].0.task.id, slow.id);
+        let tasks = store.queue(TASKS).messages::<Task>().await.unwrap();
+        let state = |id| {
+            tasks
+                .iter()
+                .find(|(task, _)| task.id == id)
+                .map(|(_, state)| *state)
+        };
+        assert_eq!(state(slow.id), Some(State::Acked));
+        assert_eq!(state(next.id), Some(State::Pending));
+    }
+}
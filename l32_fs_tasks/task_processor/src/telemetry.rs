@@ -0,0 +1,45 @@
+use anyhow::{Context, Result};
+use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use std::net::SocketAddr;
+
+pub const TASKS_PROCESSED: &str = "tasks_processed_total";
+pub const TASKS_FAILED: &str = "tasks_failed_total";
+pub const TASK_RETRIES: &str = "task_retries_total";
+pub const TASKS_IN_PROGRESS: &str = "tasks_in_progress";
+pub const TASKS_HELD: &str = "tasks_held";
+pub const TASKS_WAITING: &str = "tasks_waiting";
+pub const PROCESSING_SECONDS: &str = "task_processing_seconds";
+
+/// Buckets of processing time in seconds, from quick builtins up to long running plugins.
+const PROCESSING_BUCKETS: &[f64] = &[
+    0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 3600.0,
+];
+
+/// Serves metrics in Prometheus format on the address.
+pub fn install(addr: SocketAddr) -> Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .set_buckets_for_metric(
+            Matcher::Full(PROCESSING_SECONDS.to_string()),
+            PROCESSING_BUCKETS,
+        )
+        .context("Setting histogram buckets")?
+        .install()
+        .context("Installing metrics exporter")?;
+
+    describe_counter!(TASKS_PROCESSED, "Task files handled by the processor.");
+    describe_counter!(TASKS_FAILED, "Tasks completed with error.");
+    describe_counter!(TASK_RETRIES, "Failed attempts which are retried.");
+    describe_gauge!(TASKS_IN_PROGRESS, "Tasks which are being executed.");
+    describe_gauge!(TASKS_HELD, "Tasks which wait for their run_at time.");
+    describe_gauge!(TASKS_WAITING, "Tasks which wait for their dependencies.");
+    describe_histogram!(
+        PROCESSING_SECONDS,
+        Unit::Seconds,
+        "Time of handling a task file including retries."
+    );
+    notifier::describe_metrics();
+
+    Ok(())
+}